@@ -0,0 +1,354 @@
+use ::arrow::array::new_empty_array;
+use ::arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, Decimal128Builder, FixedSizeBinaryBuilder, Float32Builder,
+    Float64Builder, Int16Builder, Int32Builder, Int64Builder, Int8Builder, StringBuilder, Time64MicrosecondBuilder,
+    TimestampMicrosecondBuilder, UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+};
+use ::arrow::datatypes::{DataType, Date32Type, Field as ArrowField, Fields, Schema, SchemaRef, TimeUnit};
+use ::arrow::ipc::writer::{FileWriter, StreamWriter};
+use ::arrow::json::ReaderBuilder;
+use ::arrow::record_batch::RecordBatch;
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use parquet::arrow::ArrowWriter;
+use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::{Decimal, JsonValue};
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{Converter, Dec, Field, FieldKind, Nested, Options, Raw, Timezone};
+use crate::source::{Cell, Record, Source};
+
+#[derive(Args, Debug)]
+pub struct ArrowOptions {
+    /// Rows per Arrow / Parquet record batch
+    #[arg(long, default_value_t = 65536, value_parser = clap::value_parser!(u32).range(1..))]
+    pub record_batch_size: u32,
+
+    /// Decimal128 precision and scale of DECIMAL columns declared without them, as those of MySQL, SQL Server
+    /// and SQLite always are. Values with more decimal places stop the export
+    #[arg(long, value_name = "PRECISION,SCALE", default_value = "38,10", value_parser = precision_scale)]
+    pub arrow_decimal: (u8, i8),
+}
+
+/// Largest precision of Decimal128
+const DECIMAL_PRECISION: u8 = 38;
+
+fn precision_scale(value: &str) -> Result<(u8, i8), String> {
+    let (precision, scale) = value.split_once(',').ok_or("expected PRECISION,SCALE")?;
+    let precision = precision
+        .trim()
+        .parse()
+        .ok()
+        .filter(|p| (1..=DECIMAL_PRECISION).contains(p))
+        .ok_or(format!("precision must be 1 to {DECIMAL_PRECISION}"))?;
+    let scale = scale
+        .trim()
+        .parse()
+        .ok()
+        .filter(|s| (0..=precision as i8).contains(s))
+        .ok_or("scale must be 0 to the precision")?;
+    Ok((precision, scale))
+}
+
+#[macro_export]
+macro_rules! arrow_write {
+    ($ty:ty, $builder:expr) => {
+        arrow_write!($ty, $builder, |v| v)
+    };
+    ($ty:ty, $builder:expr, $fn:expr) => {
+        |rows, fld, _o| {
+            let mut builder = $builder;
+            for rw in rows.iter() {
+                builder.append_option(rw.get::<$ty>(fld.ordinal)?.map($fn));
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+    };
+}
+
+type ArrowConvFn<R> = for<'r> fn(&'r [R], &Field, &Options) -> Result<ArrayRef>;
+
+/// Arrow type of a kind, DECIMAL ones without a declared precision and scale get `decimal`
+fn data_type(kind: &FieldKind, decimal: (u8, i8)) -> DataType {
+    match kind {
+        FieldKind::INT8 => DataType::Int8,
+        FieldKind::INT16 => DataType::Int16,
+        FieldKind::INT32 => DataType::Int32,
+        FieldKind::INT64 => DataType::Int64,
+        FieldKind::UINT8 => DataType::UInt8,
+        FieldKind::UINT16 => DataType::UInt16,
+        FieldKind::UINT32 => DataType::UInt32,
+        FieldKind::UINT64 | FieldKind::BIT => DataType::UInt64,
+        FieldKind::FLOAT32 => DataType::Float32,
+        FieldKind::FLOAT64 => DataType::Float64,
+        FieldKind::STR => DataType::Utf8,
+        FieldKind::BOOL => DataType::Boolean,
+        FieldKind::DECIMAL(_) => {
+            let (precision, scale) = decimal_type(kind, decimal);
+            DataType::Decimal128(precision, scale)
+        }
+        FieldKind::DATE => DataType::Date32,
+        FieldKind::TIME => DataType::Time64(TimeUnit::Microsecond),
+        FieldKind::DATETIME => DataType::Timestamp(TimeUnit::Microsecond, None),
+        FieldKind::DATETIMETZ => DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+        FieldKind::JSON => DataType::Utf8,
+        FieldKind::UUID => DataType::FixedSizeBinary(16),
+        FieldKind::BINARY => DataType::Binary,
+        FieldKind::ARRAY(elem) => DataType::List(Arc::new(ArrowField::new("item", nested_type(elem, decimal), true))),
+        FieldKind::COMPOSITE(fields) if fields.is_empty() => DataType::Utf8,
+        FieldKind::RANGE(elem) => DataType::Struct(Fields::from(vec![
+            ArrowField::new("lower", nested_type(elem, decimal), true),
+            ArrowField::new("upper", nested_type(elem, decimal), true),
+            ArrowField::new("bounds", DataType::Utf8, false),
+        ])),
+        // ISO 8601, Parquet has no interval type holding microseconds
+        FieldKind::INTERVAL | FieldKind::NETWORK => DataType::Utf8,
+        FieldKind::COMPOSITE(fields) => DataType::Struct(
+            fields
+                .iter()
+                .map(|(name, kind)| ArrowField::new(name, nested_type(kind, decimal), true))
+                .collect::<Fields>(),
+        ),
+        FieldKind::UNKNOWN(_) => DataType::Utf8,
+    }
+}
+
+/// Type of array elements and composite fields, those kept as text by `Nested` stay Utf8
+fn nested_type(kind: &FieldKind, decimal: (u8, i8)) -> DataType {
+    match kind {
+        FieldKind::JSON | FieldKind::UUID | FieldKind::BINARY => DataType::Utf8,
+        kind => data_type(kind, decimal),
+    }
+}
+
+/// Turn the values of Utf8 elements into text, leaving the rest to the Arrow JSON decoder,
+/// which is only given DECIMAL elements it does not have to round
+fn nested_value(value: JsonValue, kind: &FieldKind, decimal: (u8, i8)) -> Result<JsonValue> {
+    Ok(match (value, kind) {
+        (JsonValue::Array(items), FieldKind::ARRAY(elem)) => {
+            items.into_iter().map(|v| nested_value(v, elem, decimal)).collect::<Result<_>>()?
+        }
+        (JsonValue::Object(mut map), FieldKind::COMPOSITE(fields)) if !fields.is_empty() => {
+            for (name, kind) in fields {
+                if let Some(v) = map.get_mut(name) {
+                    *v = nested_value(v.take(), kind, decimal)?;
+                }
+            }
+            map.into()
+        }
+        (JsonValue::Object(mut map), FieldKind::RANGE(elem)) => {
+            for name in ["lower", "upper"] {
+                if let Some(v) = map.get_mut(name) {
+                    *v = nested_value(v.take(), elem, decimal)?;
+                }
+            }
+            map.into()
+        }
+        (value @ (JsonValue::Null | JsonValue::String(_)), _) => value,
+        (JsonValue::Number(v), FieldKind::DECIMAL(_)) => {
+            let (precision, scale) = decimal_type(kind, decimal);
+            decimal_mantissa(Dec::Number(v.as_str().parse()?), precision, scale)?;
+            JsonValue::Number(v)
+        }
+        (value, kind) if nested_type(kind, decimal) == DataType::Utf8 => value.to_string().into(),
+        (value, _) => value,
+    })
+}
+
+/// Arrays and composites go through the Arrow JSON decoder, which builds list and struct arrays.
+/// It is fed JSON text, the serde route would turn exact decimal numbers into objects
+fn nested<R>(rows: &[R], fld: &Field, opts: &Options) -> Result<ArrayRef>
+where
+    R: for<'r> Cell<'r, Nested>,
+{
+    let decimal = opts.arrow.arrow_decimal;
+    let data_type = data_type(&fld.kind, decimal);
+    let schema = Schema::new(vec![ArrowField::new("value", data_type.clone(), true)]);
+    let mut decoder = ReaderBuilder::new(Arc::new(schema)).with_batch_size(rows.len().max(1)).build_decoder()?;
+    let values = rows
+        .iter()
+        .map(|rw| {
+            let value = rw
+                .get::<Nested>(fld.ordinal)?
+                .map_or(Ok(JsonValue::Null), |v| nested_value(v.json(Timezone::Local), &fld.kind, decimal))
+                .map_err(|e| anyhow!("Column \"{}\": {e}", fld.name))?;
+            Ok(serde_json::json!({ "value": value }).to_string())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    decoder.decode(values.join("\n").as_bytes())?;
+    Ok(match decoder.flush()? {
+        Some(batch) => batch.column(0).clone(),
+        None => new_empty_array(&data_type),
+    })
+}
+
+fn schema(columns: &[Field], decimal: (u8, i8)) -> SchemaRef {
+    Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|fld| {
+                let af = ArrowField::new(&fld.name, data_type(&fld.kind, decimal), true);
+                match fld.kind {
+                    FieldKind::JSON => af.with_metadata([("ARROW:extension:name", "arrow.json")]),
+                    FieldKind::UUID => af.with_metadata([("ARROW:extension:name", "arrow.uuid")]),
+                    _ => af,
+                }
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn time_micros(v: NaiveTime) -> i64 {
+    (v - NaiveTime::MIN).num_microseconds().unwrap_or_default()
+}
+
+/// Precision and scale of a DECIMAL column, the declared ones when Decimal128 holds them
+fn decimal_type(kind: &FieldKind, decimal: (u8, i8)) -> (u8, i8) {
+    match *kind {
+        FieldKind::DECIMAL(Some((precision, scale)))
+            if (1..=DECIMAL_PRECISION as u16).contains(&precision)
+                && (0..=precision.min(Decimal::MAX_SCALE as u16) as i16).contains(&scale) =>
+        {
+            (precision as u8, scale as i8)
+        }
+        _ => decimal,
+    }
+}
+
+/// Unscaled value at the column's scale, refusing values with more decimal places than it holds
+/// and SQLite text which is no number
+fn decimal_mantissa(v: Dec, precision: u8, scale: i8) -> Result<i128> {
+    let v = match v {
+        Dec::Number(v) => v,
+        Dec::Text(text) => bail!("DECIMAL value \"{text}\" is not a number Decimal128 can hold"),
+    };
+    let scale = scale as u32;
+    let v = if v.scale() > scale { v.normalize() } else { v };
+    if v.scale() > scale {
+        bail!("DECIMAL value {v} has more than {scale} decimal places (see --arrow-decimal)");
+    }
+    v.mantissa()
+        .checked_mul(10i128.pow(scale - v.scale()))
+        .filter(|m| m.unsigned_abs() < 10u128.pow(precision as u32))
+        .ok_or_else(|| anyhow!("DECIMAL value {v} does not fit Decimal128({precision}, {scale}) (see --arrow-decimal)"))
+}
+
+enum Sink {
+    File(FileWriter<File>),
+    Stream(StreamWriter<File>),
+    Parquet(ArrowWriter<File>),
+}
+
+impl Sink {
+    fn new(output: &Path, schema: &SchemaRef) -> Result<Self> {
+        let file = File::create(output)?;
+        Ok(match output.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => Sink::Parquet(ArrowWriter::try_new(file, schema.clone(), None)?),
+            Some("arrows") => Sink::Stream(StreamWriter::try_new(file, schema)?),
+            _ => Sink::File(FileWriter::try_new(file, schema)?),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            Sink::File(wr) => wr.write(batch)?,
+            Sink::Stream(wr) => wr.write(batch)?,
+            Sink::Parquet(wr) => wr.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Sink::File(mut wr) => wr.finish()?,
+            Sink::Stream(mut wr) => wr.finish()?,
+            Sink::Parquet(wr) => {
+                wr.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ARROW<DB: Source> {
+    phantom: PhantomData<DB>,
+}
+
+impl<DB: Source> Converter<DB> for ARROW<DB> {
+    type ConvFn = ArrowConvFn<DB::Row>;
+
+    fn convert(field: &Field, _opts: &Options) -> Self::ConvFn {
+        match field.kind {
+            FieldKind::INT8 => arrow_write!(i8, Int8Builder::new()),
+            FieldKind::INT16 => arrow_write!(i16, Int16Builder::new()),
+            FieldKind::INT32 => arrow_write!(i32, Int32Builder::new()),
+            FieldKind::INT64 => arrow_write!(i64, Int64Builder::new()),
+            FieldKind::UINT8 => arrow_write!(u8, UInt8Builder::new()),
+            FieldKind::UINT16 => arrow_write!(u16, UInt16Builder::new()),
+            FieldKind::UINT32 => arrow_write!(u32, UInt32Builder::new()),
+            FieldKind::UINT64 | FieldKind::BIT => arrow_write!(u64, UInt64Builder::new()),
+            FieldKind::FLOAT32 => arrow_write!(f32, Float32Builder::new()),
+            FieldKind::FLOAT64 => arrow_write!(f64, Float64Builder::new()),
+            FieldKind::STR => arrow_write!(&str, StringBuilder::new()),
+            FieldKind::BOOL => arrow_write!(bool, BooleanBuilder::new()),
+            FieldKind::DECIMAL(_) => |rows, fld, o| {
+                let (precision, scale) = decimal_type(&fld.kind, o.arrow.arrow_decimal);
+                let mut builder = Decimal128Builder::new().with_precision_and_scale(precision, scale)?;
+                for rw in rows.iter() {
+                    let v = rw.get::<Dec>(fld.ordinal)?.map(|v| decimal_mantissa(v, precision, scale));
+                    builder.append_option(v.transpose().map_err(|e| anyhow!("Column \"{}\": {e}", fld.name))?);
+                }
+                Ok(Arc::new(builder.finish()))
+            },
+            FieldKind::DATE => arrow_write!(NaiveDate, Date32Builder::new(), Date32Type::from_naive_date),
+            FieldKind::TIME => arrow_write!(NaiveTime, Time64MicrosecondBuilder::new(), time_micros),
+            FieldKind::DATETIME => {
+                arrow_write!(NaiveDateTime, TimestampMicrosecondBuilder::new(), |v| v.and_utc().timestamp_micros())
+            }
+            FieldKind::DATETIMETZ => {
+                arrow_write!(DateTime<Local>, TimestampMicrosecondBuilder::new().with_timezone("+00:00"), |v| v
+                    .timestamp_micros())
+            }
+            FieldKind::JSON => arrow_write!(JsonValue, StringBuilder::new(), |v| v.to_string()),
+            FieldKind::UUID => |rows, fld, _o| {
+                let mut builder = FixedSizeBinaryBuilder::with_capacity(rows.len(), 16);
+                for rw in rows.iter() {
+                    match rw.get::<Uuid>(fld.ordinal)? {
+                        Some(v) => builder.append_value(v.as_bytes())?,
+                        None => builder.append_null(),
+                    }
+                }
+                Ok(Arc::new(builder.finish()))
+            },
+            FieldKind::BINARY => arrow_write!(&[u8], BinaryBuilder::new()),
+            FieldKind::ARRAY(_) | FieldKind::COMPOSITE(_) | FieldKind::RANGE(_) => nested,
+            FieldKind::INTERVAL | FieldKind::NETWORK => arrow_write!(Nested, StringBuilder::new(), |v| v.to_string()),
+            FieldKind::UNKNOWN(_) => arrow_write!(Raw, StringBuilder::new(), |v| v.to_string()),
+        }
+    }
+
+    fn write(
+        columns: &[Field],
+        mut result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let schema = schema(columns, opts.arrow.arrow_decimal);
+        let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+        let mut sink = Sink::new(output.as_ref(), &schema)?;
+        loop {
+            let rows = result.by_ref().take(opts.arrow.record_batch_size as usize).collect::<Result<Vec<_>>>()?;
+            if rows.is_empty() {
+                break;
+            }
+            let arrays =
+                convs.iter().enumerate().map(|(c, conv)| conv(&rows, &columns[c], opts)).collect::<Result<Vec<_>>>()?;
+            sink.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+        }
+        sink.finish()
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::Result;
+use num_traits::Float;
+use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::JsonValue;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use super::sql::Dialect;
+use super::{hex, Converter, Dec, Field, FieldKind, Nested, Options, Raw};
+use crate::source::{Record, Source};
+
+#[macro_export]
+macro_rules! copy_write {
+    ($ty:ty) => {
+        |c, rw, _d| Ok(rw.get::<$ty>(c)?.map(|v| v.to_string()))
+    };
+    ($ty:ty, $fn:expr) => {
+        |c, rw, d| Ok(rw.get::<$ty>(c)?.and_then(|v| $fn(d, v)))
+    };
+    ($ty:ty, fmt $fmt:literal) => {
+        |c, rw, _d| Ok(rw.get::<$ty>(c)?.map(|v| v.format($fmt).to_string()))
+    };
+}
+
+type CopyConvFn<R> = for<'r> fn(usize, &'r R, Dialect) -> Result<Option<String>>;
+
+/// Text representation of NULL shared by PostgreSQL COPY and MySQL LOAD DATA
+const NULL: &str = "\\N";
+
+fn escape(value: &str, dialect: Dialect) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\0' if dialect == Dialect::Mysql => out.push_str("\\0"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Shortest text of the value in its own precision, MySQL has no NaN or infinities to load
+fn float<T: Float + fmt::Display>(dialect: Dialect, value: T) -> Option<String> {
+    match (dialect, value.is_finite()) {
+        (_, true) => Some(value.to_string()),
+        (Dialect::Postgres, false) if value.is_nan() => Some("NaN".into()),
+        (Dialect::Postgres, false) if value.is_sign_positive() => Some("Infinity".into()),
+        (Dialect::Postgres, false) => Some("-Infinity".into()),
+        (Dialect::Mysql, false) => None,
+    }
+}
+
+/// bytea hex input for PostgreSQL, bare hex for MySQL which decodes it with UNHEX in the LOAD DATA statement
+fn binary(dialect: Dialect, value: &[u8]) -> Option<String> {
+    Some(match dialect {
+        Dialect::Postgres => format!("\\x{}", hex(value)),
+        Dialect::Mysql => hex(value),
+    })
+}
+
+fn boolean(dialect: Dialect, value: bool) -> Option<String> {
+    Some(match (dialect, value) {
+        (Dialect::Postgres, true) => "t".into(),
+        (Dialect::Postgres, false) => "f".into(),
+        (Dialect::Mysql, value) => u8::from(value).to_string(),
+    })
+}
+
+pub struct COPY<DB: Source> {
+    phantom: PhantomData<DB>,
+}
+
+impl<DB: Source> Converter<DB> for COPY<DB> {
+    type ConvFn = CopyConvFn<DB::Row>;
+
+    fn convert(field: &Field, _opts: &Options) -> Self::ConvFn {
+        match field.kind {
+            FieldKind::INT8 => copy_write!(i8),
+            FieldKind::INT16 => copy_write!(i16),
+            FieldKind::INT32 => copy_write!(i32),
+            FieldKind::INT64 => copy_write!(i64),
+            FieldKind::UINT8 => copy_write!(u8),
+            FieldKind::UINT16 => copy_write!(u16),
+            FieldKind::UINT32 => copy_write!(u32),
+            FieldKind::UINT64 | FieldKind::BIT => copy_write!(u64),
+            FieldKind::FLOAT32 => copy_write!(f32, float),
+            FieldKind::FLOAT64 => copy_write!(f64, float),
+            FieldKind::STR => copy_write!(&str),
+            FieldKind::BOOL => copy_write!(bool, boolean),
+            FieldKind::DECIMAL(_) => copy_write!(Dec),
+            FieldKind::DATE => copy_write!(NaiveDate, fmt "%Y-%m-%d"),
+            FieldKind::TIME => copy_write!(NaiveTime, fmt "%H:%M:%S%.f"),
+            FieldKind::DATETIME => copy_write!(NaiveDateTime, fmt "%Y-%m-%d %H:%M:%S%.f"),
+            FieldKind::DATETIMETZ => copy_write!(DateTime<Local>, fmt "%Y-%m-%d %H:%M:%S%.f%:z"),
+            FieldKind::JSON => copy_write!(JsonValue),
+            FieldKind::UUID => copy_write!(Uuid),
+            FieldKind::BINARY => copy_write!(&[u8], binary),
+            FieldKind::ARRAY(_)
+            | FieldKind::COMPOSITE(_)
+            | FieldKind::RANGE(_)
+            | FieldKind::INTERVAL
+            | FieldKind::NETWORK => copy_write!(Nested),
+            FieldKind::UNKNOWN(_) => copy_write!(Raw),
+        }
+    }
+
+    fn companions(output: &Path, opts: &Options) -> Vec<PathBuf> {
+        match Dialect::new::<DB>(opts.sql.dialect) {
+            Dialect::Mysql => vec![output.with_extension("load.sql")],
+            Dialect::Postgres => Vec::new(),
+        }
+    }
+
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let so = &opts.sql;
+        let output = output.as_ref();
+        let dialect = Dialect::new::<DB>(so.dialect);
+        let table = dialect.quote_table(
+            &so.table.clone().unwrap_or_else(|| output.file_stem().unwrap_or_default().to_string_lossy().into_owned()),
+        );
+        let names = match columns.is_empty() {
+            true => String::new(),
+            false => format!(
+                " ({})",
+                columns.iter().map(|fld| dialect.quote_ident(&fld.name)).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        let mut cf = BufWriter::new(File::create(output)?);
+        match dialect {
+            // psql script: COPY header, data, end-of-data marker
+            Dialect::Postgres => writeln!(cf, "COPY {table}{names} FROM STDIN;")?,
+            // bare data file, the LOAD DATA statement goes to a companion <stem>.load.sql file, named apart from
+            // the SQL output of the same query
+            Dialect::Mysql => {
+                // binary columns go through user variables to be decoded from hex
+                let mut names = Vec::new();
+                let mut sets = Vec::new();
+                for fld in columns {
+                    let name = dialect.quote_ident(&fld.name);
+                    match fld.kind {
+                        FieldKind::BINARY => {
+                            names.push(format!("@{name}"));
+                            sets.push(format!("{name} = UNHEX(@{name})"));
+                        }
+                        _ => names.push(name),
+                    }
+                }
+                let names = match names.is_empty() {
+                    true => String::new(),
+                    false => format!(" ({})", names.join(", ")),
+                };
+                let sets = match sets.is_empty() {
+                    true => String::new(),
+                    false => format!(" SET {}", sets.join(", ")),
+                };
+                let mut sf = File::create(output.with_extension("load.sql"))?;
+                let path = output.canonicalize()?;
+                writeln!(
+                    sf,
+                    "LOAD DATA LOCAL INFILE {} INTO TABLE {table} CHARACTER SET utf8mb4 \
+                     FIELDS TERMINATED BY '\\t' ESCAPED BY '\\\\' LINES TERMINATED BY '\\n'{names}{sets};",
+                    dialect.quote_literal(&path.to_string_lossy())
+                )?;
+            }
+        }
+        let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+        for rw in result {
+            let rw = rw?;
+            let values = convs
+                .iter()
+                .enumerate()
+                .map(|(c, conv)| {
+                    Ok(conv(columns[c].ordinal, &rw, dialect)?.map_or_else(|| NULL.into(), |v| escape(&v, dialect)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            writeln!(cf, "{}", values.join("\t"))?;
+        }
+        if dialect == Dialect::Postgres {
+            writeln!(cf, "\\.")?;
+        }
+        cf.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::JsonValue;
+use std::marker::PhantomData;
+use std::path::Path;
+use uuid::Uuid;
+
+use super::{Bits, Converter, Dec, Field, FieldKind, Nested, Options, Raw};
+use crate::source::{Record, Source};
+
+#[derive(Args, Debug)]
+pub struct CsvOptions {
+    /// CSV field delimiter [default: ',' or TAB for .tsv]
+    #[arg(long)]
+    pub csv_delimiter: Option<char>,
+
+    /// CSV quote character
+    #[arg(long, default_value_t = '"')]
+    pub csv_quote: char,
+
+    /// CSV escape character for quotes (doubled quotes if not set)
+    #[arg(long)]
+    pub csv_escape: Option<char>,
+
+    /// CSV line terminator
+    #[arg(long, value_enum, default_value_t = Terminator::Crlf)]
+    pub csv_terminator: Terminator,
+
+    /// Do not write CSV header row
+    #[arg(long)]
+    pub csv_no_header: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Terminator {
+    Crlf,
+    Lf,
+    Cr,
+}
+
+#[macro_export]
+macro_rules! csv_write {
+    ($ty:ty) => {
+        |c, rw, _o| Ok(rw.get::<$ty>(c)?.map(|v| v.to_string()))
+    };
+}
+
+type CsvConvFn<R> = for<'r> fn(usize, &'r R, &Options) -> Result<Option<String>>;
+
+fn ascii(name: &str, ch: char) -> Result<u8> {
+    u8::try_from(ch).ok().filter(u8::is_ascii).ok_or_else(|| anyhow!("CSV {name} must be an ASCII character"))
+}
+
+pub struct CSV<DB: Source> {
+    phantom: PhantomData<DB>,
+}
+
+impl<DB: Source> Converter<DB> for CSV<DB> {
+    type ConvFn = CsvConvFn<DB::Row>;
+
+    fn convert(field: &Field, opts: &Options) -> Self::ConvFn {
+        match field.kind {
+            FieldKind::INT8 => csv_write!(i8),
+            FieldKind::INT16 => csv_write!(i16),
+            FieldKind::INT32 => csv_write!(i32),
+            FieldKind::INT64 => csv_write!(i64),
+            FieldKind::UINT8 => csv_write!(u8),
+            FieldKind::UINT16 => csv_write!(u16),
+            FieldKind::UINT32 => csv_write!(u32),
+            FieldKind::BIT if opts.bit == Bits::Text => |c, rw, _o| Ok(rw.get::<u64>(c)?.map(|v| format!("{v:b}"))),
+            FieldKind::UINT64 | FieldKind::BIT => csv_write!(u64),
+            FieldKind::FLOAT32 => csv_write!(f32),
+            FieldKind::FLOAT64 => csv_write!(f64),
+            FieldKind::STR => csv_write!(&str),
+            FieldKind::BOOL => csv_write!(bool),
+            FieldKind::DECIMAL(_) => csv_write!(Dec),
+            FieldKind::DATE => csv_write!(NaiveDate),
+            FieldKind::TIME => csv_write!(NaiveTime),
+            FieldKind::DATETIME => csv_write!(NaiveDateTime),
+            FieldKind::DATETIMETZ => {
+                |c, rw, o| Ok(rw.get::<DateTime<Local>>(c)?.map(|v| o.timezone.apply(v).to_string()))
+            }
+            FieldKind::JSON => csv_write!(JsonValue),
+            FieldKind::UUID => csv_write!(Uuid),
+            FieldKind::BINARY => |c, rw, o| Ok(rw.get::<&[u8]>(c)?.map(|v| o.binary.encode(v))),
+            FieldKind::ARRAY(_)
+            | FieldKind::COMPOSITE(_)
+            | FieldKind::RANGE(_)
+            | FieldKind::INTERVAL
+            | FieldKind::NETWORK => csv_write!(Nested),
+            FieldKind::UNKNOWN(_) => |c, rw, o| Ok(rw.get::<Raw>(c)?.map(|v| v.encode(o.binary))),
+        }
+    }
+
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let co = &opts.csv;
+        let tsv = output.as_ref().extension().is_some_and(|ext| ext == "tsv");
+        let delimiter = co.csv_delimiter.unwrap_or(if tsv { '\t' } else { ',' });
+        let mut builder = ::csv::WriterBuilder::new();
+        builder.delimiter(ascii("delimiter", delimiter)?).quote(ascii("quote", co.csv_quote)?).terminator(
+            match co.csv_terminator {
+                Terminator::Crlf => ::csv::Terminator::CRLF,
+                Terminator::Lf => ::csv::Terminator::Any(b'\n'),
+                Terminator::Cr => ::csv::Terminator::Any(b'\r'),
+            },
+        );
+        if let Some(escape) = co.csv_escape {
+            builder.double_quote(false).escape(ascii("escape", escape)?);
+        }
+        let mut wr = builder.from_path(output)?;
+        let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+        if !co.csv_no_header && !columns.is_empty() {
+            wr.write_record(columns.iter().map(|fld| &fld.name))?;
+        }
+        for rw in result {
+            let rw = rw?;
+            let values = convs
+                .iter()
+                .enumerate()
+                .map(|(c, conv)| Ok(conv(columns[c].ordinal, &rw, opts)?.unwrap_or_else(|| opts.null.clone())))
+                .collect::<Result<Vec<_>>>()?;
+            wr.write_record(values)?;
+        }
+        wr.flush()?;
+        Ok(())
+    }
+}
@@ -1,131 +1,388 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, ValueEnum};
 use num_traits::ToPrimitive;
-use serde_json::{Map, Value};
+use serde_json::{json, Map, Value};
 use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
-use sqlx::types::{Decimal, JsonValue};
-use sqlx::{ColumnIndex, Database, Decode, Type, Row};
+use sqlx::types::JsonValue;
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
 use std::path::Path;
-use std::io::Write;
+use uuid::Uuid;
 
-use super::{Field, Converter, FieldKind};
+use super::{float_json, BigUint, Bits, Converter, Dec, Decimals, Field, FieldKind, Nested, Options, Raw};
+use crate::source::{Record, Source};
 
 #[macro_export]
 macro_rules! json_write {
     ($ty:ty) => {
-        |c, rw| rw.get::<$ty, _>(c).into()
+        |c, rw, _o| Ok(rw.get::<$ty>(c)?.into())
     };
 }
 
 #[macro_export]
 macro_rules! json_write_date {
     ($ty:ty) => {
-        //|c, rw| rw.get::<$ty, _>(c).map(|d| d.format("%+").to_string()).into()
-        |c, rw| rw.get::<$ty, _>(c).map(|d| d.to_string()).into()
+        |c, rw, _o| Ok(rw.get::<$ty>(c)?.map(|d| d.to_string()).into())
     };
 }
 
+#[derive(Args, Debug)]
+pub struct JsonOptions {
+    /// Indent JSON output, JSON Lines (.jsonl, .ndjson) keeps one object per line
+    #[arg(long)]
+    pub json_pretty: bool,
+
+    /// Shape of JSON output
+    #[arg(long, value_enum, default_value_t = Layout::Objects)]
+    pub json_layout: Layout,
+
+    /// Nest the values of columns named like `customer.address.city` or `items[0].sku` into objects and arrays
+    #[arg(long)]
+    pub json_nest: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// An object per row
+    Objects,
+    /// An array per row, after one with the column names
+    Arrays,
+    /// An object with an array of values per column, held in memory until the last row
+    Columns,
+    /// `{"schema": [{"name", "kind"}…], "rows": [[…]…]}`
+    Envelope,
+}
+
+/// Streams the elements of a JSON array, or the lines of JSON Lines output
+struct Elements<W: Write> {
+    wr: W,
+    lines: bool,
+    /// Nesting level of the array when pretty printing
+    depth: Option<usize>,
+    count: usize,
+}
+
+/// Pretty printed text of a value nested `depth` levels deep
+fn pretty(value: &Value, depth: usize) -> Result<String> {
+    Ok(serde_json::to_string_pretty(value)?.replace('\n', &format!("\n{}", "  ".repeat(depth))))
+}
+
+impl<W: Write> Elements<W> {
+    fn open(mut wr: W, lines: bool, depth: Option<usize>) -> Result<Self> {
+        if !lines {
+            write!(wr, "[")?;
+        }
+        Ok(Self { wr, lines, depth, count: 0 })
+    }
+
+    fn push(&mut self, value: &Value) -> Result<()> {
+        if !self.lines {
+            // elements on their own lines, compact ones unindented
+            let indent = self.depth.map_or(0, |depth| depth + 1);
+            write!(self.wr, "{}\n{}", if self.count > 0 { "," } else { "" }, "  ".repeat(indent))?;
+        }
+        match self.depth {
+            Some(depth) => write!(self.wr, "{}", pretty(value, depth + 1)?)?,
+            None => serde_json::to_writer(&mut self.wr, value)?,
+        }
+        if self.lines {
+            writeln!(self.wr)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<W> {
+        if !self.lines {
+            if self.count > 0 {
+                write!(self.wr, "\n{}", "  ".repeat(self.depth.unwrap_or_default()))?;
+            }
+            write!(self.wr, "]")?;
+        }
+        Ok(self.wr)
+    }
+}
+
+/// Step of the path a column is nested at by `--json-nest`
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Path of a column name: `customer.address.city`, `items[0].sku`
+fn steps(name: &str) -> Result<Vec<Step>> {
+    let invalid = || anyhow!("Column \"{name}\" is not a valid path for --json-nest");
+    let mut steps = Vec::new();
+    for part in name.split('.') {
+        let (key, mut indexes) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        steps.push(Step::Key(key.into()));
+        while let Some(rest) = indexes.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            steps.push(Step::Index(index.parse().map_err(|_| invalid())?));
+            indexes = rest;
+        }
+        if !indexes.is_empty() {
+            return Err(invalid());
+        }
+    }
+    Ok(steps)
+}
+
+/// Paths of the columns, refusing those which would overwrite each other
+fn nesting(columns: &[Field]) -> Result<Vec<Vec<Step>>> {
+    let paths = columns.iter().map(|fld| steps(&fld.name)).collect::<Result<Vec<_>>>()?;
+    for (a, path_a) in paths.iter().enumerate() {
+        for (b, path_b) in paths.iter().enumerate().skip(a + 1) {
+            let common = path_a.iter().zip(path_b).take_while(|(step_a, step_b)| step_a == step_b).count();
+            // fine when they part at two keys or two indexes, not when one path holds the other
+            // or an object and an array would share a place
+            let apart = matches!(
+                (path_a.as_slice().get(common), path_b.as_slice().get(common)),
+                (Some(Step::Key(_)), Some(Step::Key(_))) | (Some(Step::Index(_)), Some(Step::Index(_)))
+            );
+            if !apart {
+                bail!("Columns \"{}\" and \"{}\" collide in nested JSON", columns[a].name, columns[b].name);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Put a value at its path, creating the objects and arrays on the way
+fn nest(node: &mut Value, path: &[Step], value: Value) {
+    let Some((step, rest)) = path.split_first() else {
+        *node = value;
+        return;
+    };
+    let child = match (step, node) {
+        (Step::Key(key), Value::Object(map)) => map.entry(key.clone()).or_insert(Value::Null),
+        (Step::Index(i), Value::Array(items)) => {
+            if items.len() <= *i {
+                items.resize(i + 1, Value::Null);
+            }
+            &mut items[*i]
+        }
+        (step, node) => {
+            *node = match step {
+                Step::Key(_) => Value::Object(JsonMap::new()),
+                Step::Index(_) => Value::Array(Vec::new()),
+            };
+            return nest(node, path, value);
+        }
+    };
+    nest(child, rest, value);
+}
+
 type JsonMap = Map<String, Value>;
-type JsonConvFn<'a, R> = fn(usize, &'a R) -> Value;
+type JsonConvFn<R> = for<'r> fn(usize, &'r R, &Options) -> Result<Value>;
 
-pub struct JSON<DB: Database> {
+pub struct JSON<DB: Source> {
     phantom: PhantomData<DB>,
 }
 
-impl<'a, DB: Database> Converter<'a, DB> for JSON<DB> {
-    type ConvFn = JsonConvFn<'a, DB::Row>;
-
-    fn convert(field: &Field) -> Self::ConvFn
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>,
-    {
+impl<DB: Source> Converter<DB> for JSON<DB> {
+    type ConvFn = JsonConvFn<DB::Row>;
+
+    fn convert(field: &Field, opts: &Options) -> Self::ConvFn {
         match field.kind {
             FieldKind::INT8 => json_write!(i8),
             FieldKind::INT16 => json_write!(i16),
             FieldKind::INT32 => json_write!(i32),
             FieldKind::INT64 => json_write!(i64),
-            FieldKind::UINT8 => todo!(),
-            FieldKind::UINT16 => todo!(),
-            FieldKind::UINT32 => todo!(),
-            FieldKind::UINT64 => todo!(),
-            FieldKind::FLOAT32 => json_write!(f32),
-            FieldKind::FLOAT64 => json_write!(f64),
+            FieldKind::UINT8 => json_write!(u8),
+            FieldKind::UINT16 => json_write!(u16),
+            FieldKind::UINT32 => json_write!(u32),
+            FieldKind::BIT if opts.bit == Bits::Text => {
+                |c, rw, _o| Ok(rw.get::<u64>(c)?.map(|v| format!("{v:b}")).into())
+            }
+            FieldKind::UINT64 | FieldKind::BIT => match opts.big_uint {
+                BigUint::Number => json_write!(u64),
+                BigUint::Text => |c, rw, _o| Ok(rw.get::<u64>(c)?.map(|v| v.to_string()).into()),
+            },
+            // NaN and infinities as text, the same as in arrays
+            FieldKind::FLOAT32 => |c, rw, _o| Ok(rw.get::<f32>(c)?.map_or(Value::Null, float_json)),
+            FieldKind::FLOAT64 => |c, rw, _o| Ok(rw.get::<f64>(c)?.map_or(Value::Null, float_json)),
             FieldKind::STR => json_write!(&str),
             FieldKind::BOOL => json_write!(bool),
-            FieldKind::DECIMAL => |c, rw| rw.get::<Decimal, _>(c).to_f64().unwrap().into(),
-            FieldKind::DATE => json_write_date!(Option<NaiveDate>),
-            FieldKind::TIME => json_write_date!(Option<NaiveTime>),
-            FieldKind::DATETIME => json_write_date!(Option<NaiveDateTime>),
-            FieldKind::DATETIMETZ => json_write_date!(Option<DateTime<Local>>),
+            FieldKind::DECIMAL(_) => match opts.decimal {
+                Decimals::Number => |c, rw, _o| Ok(rw.get::<Dec>(c)?.map(|v| v.json()).into()),
+                Decimals::Text => |c, rw, _o| Ok(rw.get::<Dec>(c)?.map(|v| v.to_string()).into()),
+                Decimals::Float => |c, rw, _o| {
+                    Ok(match rw.get::<Dec>(c)? {
+                        Some(Dec::Number(v)) => v.to_f64().into(),
+                        v => v.map(|v| v.json()).into(),
+                    })
+                },
+            },
+            FieldKind::DATE => json_write_date!(NaiveDate),
+            FieldKind::TIME => json_write_date!(NaiveTime),
+            FieldKind::DATETIME => json_write_date!(NaiveDateTime),
+            FieldKind::DATETIMETZ => {
+                |c, rw, o| Ok(rw.get::<DateTime<Local>>(c)?.map(|v| o.timezone.apply(v).to_rfc3339()).into())
+            }
             FieldKind::JSON => json_write!(JsonValue),
-            FieldKind::UNKNOWN(_) => todo!(),
+            FieldKind::UUID => |c, rw, _o| Ok(rw.get::<Uuid>(c)?.map(|v| v.to_string()).into()),
+            FieldKind::BINARY => |c, rw, o| Ok(rw.get::<&[u8]>(c)?.map(|v| o.binary.encode(v)).into()),
+            FieldKind::ARRAY(_)
+            | FieldKind::COMPOSITE(_)
+            | FieldKind::RANGE(_)
+            | FieldKind::INTERVAL
+            | FieldKind::NETWORK => |c, rw, o| Ok(rw.get::<Nested>(c)?.map(|v| v.json(o.timezone)).into()),
+            FieldKind::UNKNOWN(_) => |c, rw, o| Ok(rw.get::<Raw>(c)?.map(|v| v.encode(o.binary)).into()),
         }
     }
 
-    fn write(result: &[DB::Row], output: impl AsRef<Path>) -> Result<()>
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>,
-        for<'b> &'b DB::Column: Into<Field>,
-    {
-        let mut jf = File::create(output)?;
-        writeln!(jf, "[")?;
-        if !result.is_empty() {
-            let columns: Vec<Field> = result[0].columns().iter().map(|c| c.into()).collect();
-            let convs = columns
-                .iter()
-                .enumerate()
-                .map(|(_c, fld)| Self::convert(fld)).collect::<Vec<_>>();
-            for rw in result.iter() {
-                let ji = convs
-                    .iter()
-                    .enumerate()
-                    .map(|(c, conv)| (columns[c].name.clone(), conv(c, rw))
-                    );
-                let jr = JsonMap::from_iter(ji);
-                serde_json::to_writer(&jf, &jr)?;
-                writeln!(jf, ",")?;
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let jo = &opts.json;
+        // JSON Lines: one value per line, nothing around them
+        let lines = output.as_ref().extension().is_some_and(|ext| ext == "jsonl" || ext == "ndjson");
+        if lines && matches!(jo.json_layout, Layout::Columns | Layout::Envelope) {
+            bail!("JSON Lines output takes --json-layout objects or arrays");
+        }
+        if jo.json_nest && jo.json_layout != Layout::Objects {
+            bail!("--json-nest needs --json-layout objects");
+        }
+        let paths = jo.json_nest.then(|| nesting(columns)).transpose()?;
+        let depth = (jo.json_pretty && !lines).then_some(0);
+        let mut jf = BufWriter::new(File::create(output)?);
+        let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+        let values = |rw: &DB::Row| {
+            convs.iter().enumerate().map(|(c, conv)| conv(columns[c].ordinal, rw, opts)).collect::<Result<Vec<_>>>()
+        };
+        match jo.json_layout {
+            Layout::Objects => {
+                let mut rows = Elements::open(&mut jf, lines, depth)?;
+                for rw in result {
+                    let values = values(&rw?)?;
+                    let jr = match &paths {
+                        Some(paths) => {
+                            let mut jr = Value::Object(JsonMap::new());
+                            for (path, v) in paths.iter().zip(values) {
+                                nest(&mut jr, path, v);
+                            }
+                            jr
+                        }
+                        None => {
+                            Value::Object(JsonMap::from_iter(columns.iter().map(|fld| fld.name.clone()).zip(values)))
+                        }
+                    };
+                    rows.push(&jr)?;
+                }
+                rows.close()?;
+            }
+            Layout::Arrays => {
+                let mut rows = Elements::open(&mut jf, lines, depth)?;
+                if !columns.is_empty() {
+                    rows.push(&columns.iter().map(|fld| fld.name.as_str()).collect())?;
+                }
+                for rw in result {
+                    rows.push(&Value::Array(values(&rw?)?))?;
+                }
+                rows.close()?;
+            }
+            Layout::Columns => {
+                let mut data = vec![Vec::new(); columns.len()];
+                for rw in result {
+                    for (c, v) in values(&rw?)?.into_iter().enumerate() {
+                        data[c].push(v);
+                    }
+                }
+                let names = columns.iter().map(|fld| fld.name.clone());
+                let jc = Value::Object(JsonMap::from_iter(names.zip(data.into_iter().map(Value::Array))));
+                match depth {
+                    Some(depth) => write!(jf, "{}", pretty(&jc, depth)?)?,
+                    None => serde_json::to_writer(&mut jf, &jc)?,
+                }
+            }
+            Layout::Envelope => {
+                let schema =
+                    columns.iter().map(|fld| json!({ "name": fld.name, "kind": fld.kind.to_string() })).collect();
+                match depth {
+                    Some(depth) => write!(jf, "{{\n  \"schema\": {},\n  \"rows\": ", pretty(&schema, depth + 1)?)?,
+                    None => write!(jf, "{{\"schema\":{},\"rows\":", serde_json::to_string(&schema)?)?,
+                }
+                let mut rows = Elements::open(&mut jf, false, depth.map(|depth| depth + 1))?;
+                for rw in result {
+                    rows.push(&Value::Array(values(&rw?)?))?;
+                }
+                rows.close()?;
+                write!(jf, "{}}}", if depth.is_some() { "\n" } else { "" })?;
             }
         }
-        writeln!(jf, "]")?;
+        if !lines {
+            writeln!(jf)?;
+        }
+        jf.flush()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Step {
+        Step::Key(name.into())
+    }
+
+    fn columns(names: &[&str]) -> Vec<Field> {
+        let field = |(ordinal, name): (usize, &&str)| Field { name: name.to_string(), kind: FieldKind::STR, ordinal };
+        names.iter().enumerate().map(field).collect()
+    }
+
+    #[test]
+    fn column_steps() {
+        assert_eq!(steps("a").unwrap(), [key("a")]);
+        assert_eq!(steps("customer.address.city").unwrap(), [key("customer"), key("address"), key("city")]);
+        assert_eq!(steps("items[0].sku").unwrap(), [key("items"), Step::Index(0), key("sku")]);
+        assert_eq!(steps("m[1][2]").unwrap(), [key("m"), Step::Index(1), Step::Index(2)]);
+        for name in ["", ".a", "a.", "a..b", "[0]", "a.[0]", "a[x]", "a[-1]", "a[0", "a[0]b"] {
+            assert!(steps(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn column_nesting() {
+        for names in [&["a.b", "a.c"][..], &["a[0]", "a[1]"], &["a[0].x", "a[1].y", "a[0].z", "b"], &["a", "b.a"]] {
+            assert!(nesting(&columns(names)).is_ok(), "{names:?}");
+        }
+        let collisions =
+            [["a", "a.b"], ["a.b", "a"], ["a[0]", "a.b"], ["a.b", "a[0]"], ["a[0]", "a[0].x"], ["a.b", "a.b"]];
+        for names in collisions {
+            let error = nesting(&columns(&names)).err().map(|e| e.to_string());
+            let expected = format!("Columns \"{}\" and \"{}\" collide in nested JSON", names[0], names[1]);
+            assert_eq!(error, Some(expected));
+        }
+    }
+
+    #[test]
+    fn nested_values() {
+        let mut node = Value::Object(Map::new());
+        for (name, value) in [
+            ("customer.address.city", json!("Paris")),
+            ("customer.name", json!("Ann")),
+            ("items[2].sku", json!(3)),
+            ("items[0].sku", json!(1)),
+            ("m[1][0]", json!(2)),
+            ("m[0][1]", json!(1)),
+        ] {
+            nest(&mut node, &steps(name).unwrap(), value);
+        }
+        let expected = json!({
+            "customer": { "address": { "city": "Paris" }, "name": "Ann" },
+            "items": [{ "sku": 1 }, null, { "sku": 3 }],
+            "m": [[null, 1], [2]],
+        });
+        assert_eq!(node, expected);
+    }
+}
@@ -1,13 +1,34 @@
+pub mod arrow;
+pub mod copy;
+pub mod csv;
+pub mod gfm;
 pub mod json;
+pub mod sql;
 pub mod xlsx;
-pub mod gfm;
 
-use anyhow::Result;
-use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
-use sqlx::types::{Decimal, JsonValue};
-use sqlx::{mysql::MySqlColumn, postgres::PgColumn, Decode, Type, TypeInfo};
-use sqlx::{Column, ColumnIndex, Database};
-use std::path::Path;
+use anyhow::{bail, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono_tz::Tz;
+use clap::{Args, ValueEnum};
+use num_traits::Float;
+use serde_json::Map;
+use sqlx::error::BoxDynError;
+use sqlx::mysql::{MySql, MySqlColumn, MySqlTypeInfo, MySqlValueRef};
+use sqlx::postgres::types::{PgInterval, PgRange, PgRecordDecoder};
+use sqlx::postgres::{PgColumn, PgTypeInfo, PgTypeKind, PgValueRef, Postgres};
+use sqlx::sqlite::{Sqlite, SqliteColumn, SqliteTypeInfo, SqliteValueRef};
+use sqlx::types::chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::Decimal;
+use sqlx::types::JsonValue;
+use sqlx::{Column, Database, Decode, Type, TypeInfo, ValueRef};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::source::{Rows, Source};
 
 pub enum FieldKind {
     INT8,
@@ -18,34 +39,46 @@ pub enum FieldKind {
     UINT16,
     UINT32,
     UINT64,
+    BIT, //u64, MySQL BIT(n)
     FLOAT32,
     FLOAT64,
     STR,
     BOOL,
-    DECIMAL,
-    DATE,       //Option<NaiveDate>
-    TIME,       //Option<NaiveTime>
-    DATETIME,   //Option<NaiveDateTime>
-    DATETIMETZ, //Option<DateTime<Local>>
-    JSON,       //JsonValue
+    DECIMAL(Option<(u16, i16)>), //Dec, declared precision and scale when the source reports them
+    DATE,                        //Option<NaiveDate>
+    TIME,                        //Option<NaiveTime>
+    DATETIME,                    //Option<NaiveDateTime>
+    DATETIMETZ,                  //Option<DateTime<Local>>
+    JSON,                        //JsonValue
+    UUID,
+    BINARY,
+    ARRAY(Box<FieldKind>),               //Nested, one-dimensional PostgreSQL arrays
+    COMPOSITE(Vec<(String, FieldKind)>), //Nested, no fields for anonymous records
+    RANGE(Box<FieldKind>),               //Nested
+    INTERVAL,                            //Nested
+    NETWORK,                             //Nested, inet / cidr / macaddr
     UNKNOWN(String),
 }
 
 fn get_common_type(name: &str) -> FieldKind {
     match name {
-        "string" | "varchar" | "tinytext" | "text" | "mediumtext" | "longtext" | "char" | "bpchar" => FieldKind::STR,
+        "string" | "varchar" | "tinytext" | "text" | "mediumtext" | "longtext" | "char" | "bpchar" | "enum" => {
+            FieldKind::STR
+        }
         "tinyint" => FieldKind::INT8,
         "int2" | "smallint" => FieldKind::INT16,
         "int4" | "int" | "mediumint" => FieldKind::INT32,
         "int8" | "bigint" => FieldKind::INT64,
         "tinyint unsigned" => FieldKind::UINT8,
-        "smallint unsigned" => FieldKind::UINT16,
+        "smallint unsigned" | "year" => FieldKind::UINT16,
         "int unsigned" | "mediumint unsigned" => FieldKind::UINT32,
         "bigint unsigned" => FieldKind::UINT64,
+        "bit" => FieldKind::BIT,
         "float4" | "float" => FieldKind::FLOAT32,
         "float8" | "double" => FieldKind::FLOAT64,
-        "decimal" | "numeric" => FieldKind::DECIMAL,
-        // binary(16) => uuid
+        "decimal" | "numeric" => FieldKind::DECIMAL(None),
+        "uuid" => FieldKind::UUID,
+        "bytea" | "binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob" => FieldKind::BINARY,
         "json" | "jsonb" => FieldKind::JSON,
         "bool" | "boolean" => FieldKind::BOOL,
         "date" => FieldKind::DATE,
@@ -54,94 +87,733 @@ fn get_common_type(name: &str) -> FieldKind {
         "timestamptz" => FieldKind::DATETIMETZ,
         //"timetz" // DEPRECATED
         //"money" // DEPRECATED
-        //"bit"
         //"varbit"
-        //"varbinary"
-        //"tinyblob"
-        //"blob"
-        //"mediumblob"
-        //"longblob"
-        //"year"
-        //"set"
-        //"enum"
         typ => FieldKind::UNKNOWN(typ.to_string()),
     }
 }
 
+/// Name of the kind, with its declared parameters: `INT32`, `DECIMAL(12,2)`, `ARRAY(STR)`, `COMPOSITE(x INT32,y STR)`
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FieldKind::INT8 => "INT8",
+            FieldKind::INT16 => "INT16",
+            FieldKind::INT32 => "INT32",
+            FieldKind::INT64 => "INT64",
+            FieldKind::UINT8 => "UINT8",
+            FieldKind::UINT16 => "UINT16",
+            FieldKind::UINT32 => "UINT32",
+            FieldKind::UINT64 => "UINT64",
+            FieldKind::BIT => "BIT",
+            FieldKind::FLOAT32 => "FLOAT32",
+            FieldKind::FLOAT64 => "FLOAT64",
+            FieldKind::STR => "STR",
+            FieldKind::BOOL => "BOOL",
+            FieldKind::DECIMAL(None) => "DECIMAL",
+            FieldKind::DECIMAL(Some((precision, scale))) => return write!(f, "DECIMAL({precision},{scale})"),
+            FieldKind::DATE => "DATE",
+            FieldKind::TIME => "TIME",
+            FieldKind::DATETIME => "DATETIME",
+            FieldKind::DATETIMETZ => "DATETIMETZ",
+            FieldKind::JSON => "JSON",
+            FieldKind::UUID => "UUID",
+            FieldKind::BINARY => "BINARY",
+            FieldKind::ARRAY(elem) => return write!(f, "ARRAY({elem})"),
+            FieldKind::COMPOSITE(fields) => {
+                let fields = fields.iter().map(|(name, kind)| format!("{name} {kind}")).collect::<Vec<_>>();
+                return write!(f, "COMPOSITE({})", fields.join(","));
+            }
+            FieldKind::RANGE(elem) => return write!(f, "RANGE({elem})"),
+            FieldKind::INTERVAL => "INTERVAL",
+            FieldKind::NETWORK => "NETWORK",
+            FieldKind::UNKNOWN(typ) => return write!(f, "UNKNOWN({typ})"),
+        };
+        name.fmt(f)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Text written for NULL values in CSV, GFM and XLSX (XLSX cells are left empty by default)
+    #[arg(long, default_value = "")]
+    pub null: String,
+
+    /// How JSON and XLSX write u64 values, which can exceed the integer precision of JavaScript and Excel numbers
+    #[arg(long, value_enum, default_value_t = BigUint::Number)]
+    pub big_uint: BigUint,
+
+    /// How JSON, GFM, CSV and XLSX write MySQL BIT values
+    #[arg(long, value_enum, default_value_t = Bits::Number)]
+    pub bit: Bits,
+
+    /// How JSON writes DECIMAL values (arrays and composites keep every digit as numbers)
+    #[arg(long, value_enum, default_value_t = Decimals::Number)]
+    pub decimal: Decimals,
+
+    /// Time zone of timestamps with time zone in JSON, GFM, CSV and XLSX: local, utc or an IANA name (Europe/Rome)
+    #[arg(long, default_value = "local")]
+    pub timezone: Timezone,
+
+    /// Columns holding UUIDs as binary(16) or char(36), comma separated. With several queries each column
+    /// needs to be in at least one of the results
+    #[arg(long, value_delimiter = ',')]
+    pub uuid: Vec<String>,
+
+    /// Text encoding of binary values in JSON, GFM, CSV and XLSX (SQL and COPY use the dialect's hex literals)
+    #[arg(long, value_enum, default_value_t = Encoding::Base64)]
+    pub binary: Encoding,
+
+    /// What to do with columns of unsupported types
+    #[arg(long, value_enum, default_value_t = Unknown::Stringify)]
+    pub unknown: Unknown,
+
+    #[command(flatten)]
+    pub json: json::JsonOptions,
+
+    #[command(flatten)]
+    pub xlsx: xlsx::XlsxOptions,
+
+    #[command(flatten)]
+    pub csv: csv::CsvOptions,
+
+    #[command(flatten)]
+    pub sql: sql::SqlOptions,
+
+    #[command(flatten)]
+    pub arrow: arrow::ArrowOptions,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BigUint {
+    Number,
+    Text,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Decimals {
+    /// Exact, every digit of the value as a JSON number
+    Number,
+    /// Exact, as a JSON string
+    Text,
+    /// Nearest double, the precision JavaScript parses numbers with anyway
+    Float,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Timezone {
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "local" => Ok(Timezone::Local),
+            "utc" => Ok(Timezone::Named(Tz::UTC)),
+            _ => name.parse().map(Timezone::Named).map_err(|_| format!("unknown time zone {name}")),
+        }
+    }
+}
+
+impl Timezone {
+    pub fn apply(self, value: DateTime<Local>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => value.fixed_offset(),
+            Timezone::Named(tz) => value.with_timezone(&tz).fixed_offset(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Bits {
+    Number,
+    /// Binary digits, without leading zeros
+    Text,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Base64,
+    Hex,
+    /// PostgreSQL bytea hex format, `\x` prefixed
+    PgHex,
+    /// Leading bytes in hex and the total length, not reversible
+    Preview,
+}
+
+/// Bytes shown by `--binary preview`
+const PREVIEW_BYTES: usize = 16;
+
+pub fn hex(value: &[u8]) -> String {
+    value.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl Encoding {
+    pub fn encode(self, value: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => BASE64_STANDARD.encode(value),
+            Encoding::Hex => hex(value),
+            Encoding::PgHex => format!("\\x{}", hex(value)),
+            Encoding::Preview if value.len() > PREVIEW_BYTES => {
+                format!("{}… ({} bytes)", hex(&value[..PREVIEW_BYTES]), value.len())
+            }
+            Encoding::Preview => format!("{} ({} bytes)", hex(value), value.len()),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Unknown {
+    /// Write values as text, or with the `--binary` encoding when they are binary
+    Stringify,
+    /// Leave the column out of the output
+    Skip,
+    /// Stop with an error naming the column
+    Fail,
+}
+
 pub struct Field {
     pub(crate) name: String,
     pub(crate) kind: FieldKind,
+    /// Position of the column in the result rows
+    pub(crate) ordinal: usize,
+}
+
+/// Apply the `--uuid` columns the result has and the `--unknown` strategy
+pub fn resolve(mut columns: Vec<Field>, opts: &Options) -> Result<Vec<Field>> {
+    for fld in columns.iter_mut().filter(|fld| opts.uuid.contains(&fld.name)) {
+        fld.kind = FieldKind::UUID;
+    }
+    // columns without a type and without a single value are harmless
+    let unknown = |fld: &Field| matches!(&fld.kind, FieldKind::UNKNOWN(typ) if typ != "null");
+    match opts.unknown {
+        Unknown::Stringify => Ok(columns),
+        Unknown::Skip => Ok(columns.into_iter().filter(|fld| !unknown(fld)).collect()),
+        Unknown::Fail => match columns.iter().find(|fld| unknown(fld)) {
+            Some(Field { name, kind: FieldKind::UNKNOWN(typ), .. }) => {
+                bail!("Column \"{name}\" has unsupported type {typ} (see --unknown)")
+            }
+            _ => Ok(columns),
+        },
+    }
+}
+
+/// Value of a column of unsupported type, as text when it reads as such or else as raw bytes
+pub enum Raw {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<&[u8]> for Raw {
+    fn from(value: &[u8]) -> Self {
+        match std::str::from_utf8(value) {
+            Ok(text) if !text.chars().any(|ch| ch.is_control() && !ch.is_whitespace()) => Raw::Text(text.into()),
+            _ => Raw::Bytes(value.into()),
+        }
+    }
+}
+
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Raw::Text(text) => text.fmt(f),
+            Raw::Bytes(bytes) => hex(bytes).fmt(f),
+        }
+    }
+}
+
+impl Raw {
+    pub fn encode(self, enc: Encoding) -> String {
+        match self {
+            Raw::Text(text) => text,
+            Raw::Bytes(bytes) => enc.encode(&bytes),
+        }
+    }
+}
+
+/// DECIMAL / NUMERIC value, decodable from drivers lacking native `Decimal` support
+pub enum Dec {
+    Number(Decimal),
+    /// SQLite value of a NUMERIC affinity column which reads as no number, kept as the text it is
+    Text(String),
+}
+
+impl Dec {
+    /// JSON number with the exact digits of the value, serde_json keeps their text as is
+    pub fn json(&self) -> JsonValue {
+        match self {
+            Dec::Number(v) => JsonValue::Number(v.to_string().parse().unwrap()),
+            Dec::Text(text) => text.as_str().into(),
+        }
+    }
+}
+
+impl fmt::Display for Dec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dec::Number(v) => v.fmt(f),
+            Dec::Text(text) => text.fmt(f),
+        }
+    }
+}
+
+macro_rules! dec_native {
+    ($($db:ident: $value:ident),*) => {
+        $(
+            impl Type<$db> for Dec {
+                fn type_info() -> <$db as Database>::TypeInfo {
+                    <Decimal as Type<$db>>::type_info()
+                }
+
+                fn compatible(ty: &<$db as Database>::TypeInfo) -> bool {
+                    <Decimal as Type<$db>>::compatible(ty)
+                }
+            }
+
+            impl<'r> Decode<'r, $db> for Dec {
+                fn decode(value: $value<'r>) -> Result<Self, BoxDynError> {
+                    <Decimal as Decode<$db>>::decode(value).map(Dec::Number)
+                }
+            }
+        )*
+    };
+}
+
+dec_native!(Postgres: PgValueRef, MySql: MySqlValueRef);
+
+/// SQLite NUMERIC affinity keeps integers and reals as such, and text which reads as neither
+impl<'r> Decode<'r, Sqlite> for Dec {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        let typ = value.type_info().name().to_string();
+        Ok(match typ.as_str() {
+            "INTEGER" => Dec::Number(<i64 as Decode<Sqlite>>::decode(value)?.into()),
+            // infinities
+            "REAL" => {
+                let v = <f64 as Decode<Sqlite>>::decode(value)?;
+                v.try_into().map_or_else(|_| Dec::Text(v.to_string()), Dec::Number)
+            }
+            _ => {
+                let text = <&str as Decode<Sqlite>>::decode(value)?;
+                text.parse().map_or_else(|_| Dec::Text(text.into()), Dec::Number)
+            }
+        })
+    }
 }
 
-pub trait Converter<'a, DB> {
+/// Value of a type SQLx has no Rust counterpart for, elements decoded according to their own type
+pub enum Nested {
+    Array(Vec<Option<Nested>>),
+    /// Fields by name, `f1`, `f2`… for anonymous records
+    Composite(Vec<(String, Option<Nested>)>),
+    /// Lower and upper bound, `None` for empty ranges
+    Range(Option<(RangeBound, RangeBound)>),
+    Interval(PgInterval),
+    Timestamp(DateTime<Local>),
+    /// Members of a MySQL SET
+    Set(Vec<String>),
+    Scalar(JsonValue),
+}
+
+type RangeBound = Bound<Box<Nested>>;
+
+/// Bracket characters of the bounds of a range
+fn range_bounds<T>(lower: &Bound<T>, upper: &Bound<T>) -> String {
+    let lower = if matches!(lower, Bound::Included(_)) { '[' } else { '(' };
+    let upper = if matches!(upper, Bound::Included(_)) { ']' } else { ')' };
+    format!("{lower}{upper}")
+}
+
+fn bound_value(bound: &RangeBound) -> Option<&Nested> {
+    match bound {
+        Bound::Included(v) | Bound::Excluded(v) => Some(v),
+        Bound::Unbounded => None,
+    }
+}
+
+/// ISO 8601 duration, with negative components as PostgreSQL writes them
+fn iso8601(v: &PgInterval) -> String {
+    let mut text = String::from("P");
+    for (value, unit) in [(v.months / 12, 'Y'), (v.months % 12, 'M'), (v.days, 'D')] {
+        if value != 0 {
+            text += &format!("{value}{unit}");
+        }
+    }
+    let micros = v.microseconds;
+    if micros != 0 || text.len() == 1 {
+        text.push('T');
+        for (value, unit) in [(micros / 3_600_000_000, 'H'), (micros / 60_000_000 % 60, 'M')] {
+            if value != 0 {
+                text += &format!("{value}{unit}");
+            }
+        }
+        let seconds = micros % 60_000_000;
+        if seconds != 0 || text.ends_with('T') {
+            let sign = if seconds < 0 { "-" } else { "" };
+            let fraction = format!("{:06}", (seconds % 1_000_000).abs());
+            let fraction = fraction.trim_end_matches('0');
+            let dot = if fraction.is_empty() { "" } else { "." };
+            text += &format!("{sign}{}{dot}{fraction}S", (seconds / 1_000_000).abs());
+        }
+    }
+    text
+}
+
+/// Text of inet / cidr addresses and macaddr / macaddr8 from their binary format
+fn network(typ: &str, value: &[u8]) -> Result<String, BoxDynError> {
+    Ok(match *value {
+        _ if typ.starts_with("MACADDR") => value.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(":"),
+        // family, mask bits, cidr flag, address length
+        [family, bits, cidr, _, ref addr @ ..] => {
+            let (addr, max) = match family {
+                2 => (Ipv4Addr::from(<[u8; 4]>::try_from(addr)?).to_string(), 32),
+                _ => (Ipv6Addr::from(<[u8; 16]>::try_from(addr)?).to_string(), 128),
+            };
+            match cidr != 0 || bits != max {
+                true => format!("{addr}/{bits}"),
+                false => addr,
+            }
+        }
+        _ => return Err("invalid inet value".into()),
+    })
+}
+
+/// JSON number of a float, or the text PostgreSQL writes for NaN and infinities, which JSON numbers cannot hold
+fn float_json<T: Float + Into<JsonValue>>(v: T) -> JsonValue {
+    match v {
+        v if v.is_nan() => "NaN".into(),
+        v if v.is_infinite() && v.is_sign_positive() => "Infinity".into(),
+        v if v.is_infinite() => "-Infinity".into(),
+        v => v.into(),
+    }
+}
+
+impl Nested {
+    /// JSON value, timestamps with time zone in RFC 3339
+    pub fn json(self, tz: Timezone) -> JsonValue {
+        let item = |v: Option<Nested>| v.map_or(JsonValue::Null, |v| v.json(tz));
+        match self {
+            Nested::Array(items) => items.into_iter().map(item).collect(),
+            Nested::Composite(fields) => {
+                fields.into_iter().map(|(name, v)| (name, item(v))).collect::<Map<_, _>>().into()
+            }
+            Nested::Range(None) => serde_json::json!({ "lower": null, "upper": null, "bounds": "empty" }),
+            Nested::Range(Some((lower, upper))) => {
+                let bounds = range_bounds(&lower, &upper);
+                let value = |bound: RangeBound| match bound {
+                    Bound::Included(v) | Bound::Excluded(v) => v.json(tz),
+                    Bound::Unbounded => JsonValue::Null,
+                };
+                serde_json::json!({ "lower": value(lower), "upper": value(upper), "bounds": bounds })
+            }
+            Nested::Interval(v) => iso8601(&v).into(),
+            Nested::Timestamp(v) => tz.apply(v).to_rfc3339().into(),
+            Nested::Set(members) => members.into(),
+            Nested::Scalar(v) => v,
+        }
+    }
+
+    /// Readable text for GFM and XLSX, elements separated by commas
+    pub fn joined(&self, tz: Timezone) -> String {
+        let text = |v: &Option<Nested>| v.as_ref().map_or_else(|| "NULL".into(), |v| v.joined(tz));
+        match self {
+            Nested::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(", "),
+            Nested::Set(members) => members.join(", "),
+            Nested::Range(Some((lower, upper))) => {
+                let bounds = range_bounds(lower, upper);
+                let value = |bound| bound_value(bound).map_or_else(String::new, |v| v.joined(tz));
+                format!("{}{}, {}{}", &bounds[..1], value(lower), value(upper), &bounds[1..])
+            }
+            Nested::Composite(fields) => {
+                format!("({})", fields.iter().map(|(_, v)| text(v)).collect::<Vec<_>>().join(", "))
+            }
+            Nested::Timestamp(v) => tz.apply(*v).to_string(),
+            v => v.to_string(),
+        }
+    }
+}
+
+/// Double quote an array element or composite field holding characters special to its literal
+fn quote_item(text: String, special: &[char], null: bool) -> String {
+    if text.is_empty()
+        || text.chars().any(|ch| special.contains(&ch) || ch.is_whitespace())
+        || (null && text.eq_ignore_ascii_case("null"))
+    {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        text
+    }
+}
+
+/// Input literal of the source database: `{a,b,c}` for arrays, `(a,b,c)` for composites, `[a,b)` for ranges
+/// and `a,b,c` for sets
+impl fmt::Display for Nested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Nested::Array(items) => {
+                let items = items.iter().map(|v| match v {
+                    Some(v) => quote_item(v.to_string(), &['{', '}', ',', '"', '\\'], true),
+                    None => "NULL".into(),
+                });
+                write!(f, "{{{}}}", items.collect::<Vec<_>>().join(","))
+            }
+            Nested::Composite(fields) => {
+                let fields = fields.iter().map(|(_, v)| match v {
+                    Some(v) => quote_item(v.to_string(), &['(', ')', ',', '"', '\\'], false),
+                    None => String::new(),
+                });
+                write!(f, "({})", fields.collect::<Vec<_>>().join(","))
+            }
+            Nested::Range(None) => "empty".fmt(f),
+            Nested::Range(Some((lower, upper))) => {
+                let bounds = range_bounds(lower, upper);
+                let value = |bound| {
+                    bound_value(bound).map_or_else(String::new, |v| {
+                        quote_item(v.to_string(), &['(', ')', '[', ']', ',', '"', '\\'], false)
+                    })
+                };
+                write!(f, "{}{},{}{}", &bounds[..1], value(lower), value(upper), &bounds[1..])
+            }
+            Nested::Interval(v) => iso8601(v).fmt(f),
+            Nested::Timestamp(v) => v.format("%Y-%m-%d %H:%M:%S%.f%:z").fmt(f),
+            Nested::Set(members) => members.join(",").fmt(f),
+            Nested::Scalar(JsonValue::String(text)) => text.fmt(f),
+            Nested::Scalar(v) => v.fmt(f),
+        }
+    }
+}
+
+impl Type<Postgres> for Nested {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("record")
+    }
+
+    fn compatible(_ty: &PgTypeInfo) -> bool {
+        true
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for Nested {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let typ = value.type_info().into_owned();
+        let scalar = |v: JsonValue| Ok(Nested::Scalar(v));
+        match FieldKind::from(&typ) {
+            FieldKind::ARRAY(_) => Ok(Nested::Array(<Vec<Option<Nested>> as Decode<Postgres>>::decode(value)?)),
+            FieldKind::COMPOSITE(fields) => {
+                // binary records start with their number of fields
+                let count = u32::from_be_bytes(value.as_bytes()?[..4].try_into()?) as usize;
+                let names = match fields.is_empty() {
+                    true => (1..=count).map(|i| format!("f{i}")).collect(),
+                    false => fields.into_iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                };
+                let mut record = PgRecordDecoder::new(value)?;
+                let fields = names.into_iter().map(|name| Ok((name, record.try_decode()?)));
+                Ok(Nested::Composite(fields.collect::<Result<_, BoxDynError>>()?))
+            }
+            FieldKind::RANGE(_) => {
+                // the empty flag, which SQLx decodes the same as unbounded
+                if value.as_bytes()?.first() == Some(&1) {
+                    return Ok(Nested::Range(None));
+                }
+                let range = <PgRange<Nested> as Decode<Postgres>>::decode(value)?;
+                Ok(Nested::Range(Some((range.start.map(Box::new), range.end.map(Box::new)))))
+            }
+            FieldKind::INTERVAL => Ok(Nested::Interval(<PgInterval as Decode<Postgres>>::decode(value)?)),
+            FieldKind::NETWORK => scalar(network(typ.name(), value.as_bytes()?)?.into()),
+            FieldKind::INT16 => scalar(<i16 as Decode<Postgres>>::decode(value)?.into()),
+            FieldKind::INT32 => scalar(<i32 as Decode<Postgres>>::decode(value)?.into()),
+            FieldKind::INT64 => scalar(<i64 as Decode<Postgres>>::decode(value)?.into()),
+            FieldKind::FLOAT32 => scalar(float_json(<f32 as Decode<Postgres>>::decode(value)?)),
+            FieldKind::FLOAT64 => scalar(float_json(<f64 as Decode<Postgres>>::decode(value)?)),
+            FieldKind::STR => scalar(<&str as Decode<Postgres>>::decode(value)?.into()),
+            FieldKind::BOOL => scalar(<bool as Decode<Postgres>>::decode(value)?.into()),
+            FieldKind::DECIMAL(_) => scalar(<Dec as Decode<Postgres>>::decode(value)?.json()),
+            FieldKind::DATE => scalar(<NaiveDate as Decode<Postgres>>::decode(value)?.to_string().into()),
+            FieldKind::TIME => scalar(<NaiveTime as Decode<Postgres>>::decode(value)?.to_string().into()),
+            FieldKind::DATETIME => scalar(<NaiveDateTime as Decode<Postgres>>::decode(value)?.to_string().into()),
+            FieldKind::DATETIMETZ => Ok(Nested::Timestamp(<DateTime<Local> as Decode<Postgres>>::decode(value)?)),
+            FieldKind::JSON => scalar(<JsonValue as Decode<Postgres>>::decode(value)?),
+            FieldKind::UUID => scalar(Uuid::from_slice(value.as_bytes()?)?.to_string().into()),
+            FieldKind::BINARY => scalar(Encoding::PgHex.encode(value.as_bytes()?).into()),
+            _ => scalar(Raw::from(value.as_bytes()?).to_string().into()),
+        }
+    }
+}
+
+/// Result of one of the queries of a run, bound for a sheet of its own
+pub struct Sheet<R> {
+    /// Sheet name, `None` for the positional query
+    pub name: Option<String>,
+    pub columns: Vec<Field>,
+    pub rows: Rows<R>,
+}
+
+pub trait Converter<DB: Source> {
     type ConvFn;
 
-    fn convert(field: &Field) -> Self::ConvFn
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>;
-
-    fn write(result: &[DB::Row], output: impl AsRef<Path>) -> Result<()>
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>,
-        for<'b> &'b DB::Column: Into<Field>,
-        //for<'b> Field: Converter<'b, DB>
-        ;
+    fn convert(field: &Field, opts: &Options) -> Self::ConvFn;
+
+    /// Files written next to the output, removed along with it when the export fails
+    fn companions(_output: &Path, _opts: &Options) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()>;
+
+    /// Write the results of the queries in order, each one fetched when the previous is done.
+    /// Only formats with sheets take more than one
+    fn write_sheets(
+        mut sheets: impl ExactSizeIterator<Item = Result<Sheet<DB::Row>>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        if sheets.len() > 1 {
+            bail!("Several queries need XLSX output, which puts each one on a sheet");
+        }
+        match sheets.next() {
+            Some(sheet) => {
+                let sheet = sheet?;
+                Self::write(&sheet.columns, sheet.rows, output, opts)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&PgTypeInfo> for FieldKind {
+    fn from(typ: &PgTypeInfo) -> Self {
+        match typ.kind() {
+            PgTypeKind::Array(elem) => FieldKind::ARRAY(Box::new(elem.into())),
+            PgTypeKind::Composite(fields) => {
+                FieldKind::COMPOSITE(fields.iter().map(|(name, typ)| (name.clone(), typ.into())).collect())
+            }
+            PgTypeKind::Range(elem) => FieldKind::RANGE(Box::new(elem.into())),
+            _ => match typ.name().to_lowercase().as_str() {
+                "timestamp" => FieldKind::DATETIME,
+                "interval" => FieldKind::INTERVAL,
+                "inet" | "cidr" | "macaddr" | "macaddr8" => FieldKind::NETWORK,
+                "record" => FieldKind::COMPOSITE(Vec::new()),
+                name => get_common_type(name),
+            },
+        }
+    }
 }
 
 impl From<&PgColumn> for Field {
     fn from(col: &PgColumn) -> Self {
-        let kind = match col.type_info().name().to_lowercase().as_str() {
-            "timestamp" => FieldKind::DATETIME,
+        Self { name: col.name().to_string(), kind: col.type_info().into(), ordinal: col.ordinal() }
+    }
+}
+
+/// Whether a MySQL column has a flag: SQLx names SET columns CHAR, only their flags tell them apart
+/// and those are private but for `Debug`
+fn mysql_flag(typ: &MySqlTypeInfo, flag: &str) -> bool {
+    format!("{typ:?}").split(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_').any(|word| word == flag)
+}
+
+impl From<&MySqlTypeInfo> for FieldKind {
+    fn from(typ: &MySqlTypeInfo) -> Self {
+        match typ.name().to_lowercase().as_str() {
+            "timestamp" => FieldKind::DATETIMETZ,
+            "set" => FieldKind::ARRAY(Box::new(FieldKind::STR)),
+            "char" if mysql_flag(typ, "SET") => FieldKind::ARRAY(Box::new(FieldKind::STR)),
             name => get_common_type(name),
-        };
-        Self { name: col.name().to_string(), kind }
+        }
     }
 }
 
 impl From<&MySqlColumn> for Field {
     fn from(col: &MySqlColumn) -> Self {
-        let kind = match col.type_info().name().to_lowercase().as_str() {
-            "timestamp" => FieldKind::DATETIMETZ,
-            name => get_common_type(name),
-        };
-        Self { name: col.name().to_string(), kind }
+        Self { name: col.name().to_string(), kind: col.type_info().into(), ordinal: col.ordinal() }
+    }
+}
+
+impl From<&SqliteTypeInfo> for FieldKind {
+    fn from(typ: &SqliteTypeInfo) -> Self {
+        // storage class of a value, or the declared type when SQLx recognises it. Declared types are mapped by
+        // `sqlite_affinity` before the rows are fetched, NULL stands for expressions and undeclared types
+        match typ.name() {
+            "INTEGER" => FieldKind::INT64,
+            "REAL" => FieldKind::FLOAT64,
+            "TEXT" => FieldKind::STR,
+            "BLOB" => FieldKind::BINARY,
+            "BOOLEAN" => FieldKind::BOOL,
+            "DATE" => FieldKind::DATE,
+            "TIME" => FieldKind::TIME,
+            "DATETIME" => FieldKind::DATETIME,
+            name => FieldKind::UNKNOWN(name.to_lowercase()),
+        }
+    }
+}
+
+/// Kind of a SQLite column from its declared type, by the affinity rules of https://sqlite.org/datatype3.html
+/// once the names SQLx decodes as booleans and dates are set apart
+pub fn sqlite_affinity(decltype: &str) -> FieldKind {
+    let decltype = decltype.trim().to_lowercase();
+    match decltype.as_str() {
+        "boolean" | "bool" => FieldKind::BOOL,
+        "date" => FieldKind::DATE,
+        "time" => FieldKind::TIME,
+        "datetime" | "timestamp" => FieldKind::DATETIME,
+        name if name.contains("int") => FieldKind::INT64,
+        name if ["char", "clob", "text"].iter().any(|part| name.contains(part)) => FieldKind::STR,
+        // no declared type, no affinity: typed from the values
+        "" => FieldKind::UNKNOWN("null".into()),
+        name if name.contains("blob") => FieldKind::BINARY,
+        name if ["real", "floa", "doub"].iter().any(|part| name.contains(part)) => FieldKind::FLOAT64,
+        _ => FieldKind::DECIMAL(None),
+    }
+}
+
+impl From<&SqliteColumn> for Field {
+    fn from(col: &SqliteColumn) -> Self {
+        Self { name: col.name().to_string(), kind: col.type_info().into(), ordinal: col.ordinal() }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mysql_flags() {
+        // the only type info SQLx lets us build with a flag set
+        let typ = MySqlTypeInfo::__enum();
+        assert!(mysql_flag(&typ, "ENUM"));
+        assert!(!mysql_flag(&typ, "SET"));
+        assert!(!mysql_flag(&typ, "ENU"));
+    }
+
+    #[test]
+    fn iso8601_intervals() {
+        let interval = |months, days, microseconds| iso8601(&PgInterval { months, days, microseconds });
+        assert_eq!(interval(0, 0, 0), "PT0S");
+        assert_eq!(interval(0, 1, 0), "P1D");
+        assert_eq!(interval(14, 3, 14_706_500_000), "P1Y2M3DT4H5M6.5S");
+        assert_eq!(interval(0, 0, 1), "PT0.000001S");
+        assert_eq!(interval(0, 0, -1_500_000), "PT-1.5S");
+        assert_eq!(interval(-13, -1, -3_600_250_000), "P-1Y-1M-1DT-1H-0.25S");
+        assert_eq!(interval(1, -2, 60_000_000), "P1M-2DT1M");
+    }
+
+    #[test]
+    fn network_addresses() {
+        let ipv6 = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert_eq!(network("INET", &[2, 32, 0, 4, 192, 168, 0, 1]).unwrap(), "192.168.0.1");
+        assert_eq!(network("INET", &[2, 24, 0, 4, 192, 168, 0, 1]).unwrap(), "192.168.0.1/24");
+        assert_eq!(network("CIDR", &[2, 8, 1, 4, 10, 0, 0, 0]).unwrap(), "10.0.0.0/8");
+        assert_eq!(network("CIDR", &[2, 32, 1, 4, 10, 0, 0, 1]).unwrap(), "10.0.0.1/32");
+        assert_eq!(network("INET", &[[3, 128, 0, 16].as_slice(), &ipv6].concat()).unwrap(), "2001:db8::1");
+        assert_eq!(network("INET", &[[3, 64, 0, 16].as_slice(), &ipv6].concat()).unwrap(), "2001:db8::1/64");
+        assert_eq!(network("CIDR", &[[3, 128, 1, 16].as_slice(), &ipv6].concat()).unwrap(), "2001:db8::1/128");
+        assert_eq!(network("MACADDR", &[8, 0, 0x2b, 1, 2, 3]).unwrap(), "08:00:2b:01:02:03");
+        assert!(network("INET", &[2, 32, 0]).is_err());
+        assert!(network("INET", &[2, 32, 0, 4, 192, 168, 0]).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use num_traits::Float;
+use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::JsonValue;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use uuid::Uuid;
+
+use super::{hex, Converter, Dec, Field, FieldKind, Nested, Options, Raw};
+use crate::source::{Record, Source};
+
+#[derive(Args, Debug)]
+pub struct SqlOptions {
+    /// Target table name [default: output file name]
+    #[arg(long)]
+    pub table: Option<String>,
+
+    /// Target SQL dialect [default: source database]
+    #[arg(long, value_enum)]
+    pub dialect: Option<Dialect>,
+
+    /// Rows per INSERT statement
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_size: u32,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+}
+
+impl Dialect {
+    pub fn new<DB: Source>(dialect: Option<Dialect>) -> Self {
+        dialect.unwrap_or(match DB::NAME {
+            "MySQL" => Dialect::Mysql,
+            _ => Dialect::Postgres,
+        })
+    }
+
+    pub fn quote_ident(&self, name: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("\"{}\"", name.replace('"', "\"\"")),
+            Dialect::Mysql => format!("`{}`", name.replace('`', "``")),
+        }
+    }
+
+    pub fn quote_table(&self, name: &str) -> String {
+        name.split('.').map(|part| self.quote_ident(part)).collect::<Vec<_>>().join(".")
+    }
+
+    pub fn quote_literal(&self, value: &str) -> String {
+        match self {
+            Dialect::Postgres => format!("'{}'", value.replace('\'', "''")),
+            Dialect::Mysql => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''").replace('\0', "\\0")),
+        }
+    }
+
+    pub fn binary(&self, value: &[u8]) -> String {
+        match self {
+            Dialect::Postgres => format!("'\\x{}'", hex(value)),
+            Dialect::Mysql => format!("X'{}'", hex(value)),
+        }
+    }
+
+    /// Shortest text of the value in its own precision, NaN and infinities quoted for PostgreSQL
+    fn float<T: Float + fmt::Display>(&self, value: T) -> String {
+        match (self, value.is_finite()) {
+            (_, true) => value.to_string(),
+            (Dialect::Postgres, false) if value.is_nan() => "'NaN'".into(),
+            (Dialect::Postgres, false) if value.is_sign_positive() => "'Infinity'".into(),
+            (Dialect::Postgres, false) => "'-Infinity'".into(),
+            (Dialect::Mysql, false) => NULL.into(),
+        }
+    }
+}
+
+const NULL: &str = "NULL";
+
+#[macro_export]
+macro_rules! sql_write {
+    ($ty:ty) => {
+        |c, rw, _d| Ok(rw.get::<$ty>(c)?.map_or_else(|| NULL.into(), |v| v.to_string()))
+    };
+    ($ty:ty, $fn:expr) => {
+        |c, rw, d| Ok(rw.get::<$ty>(c)?.map_or_else(|| NULL.into(), |v| $fn(d, v)))
+    };
+}
+
+#[macro_export]
+macro_rules! sql_quote {
+    ($ty:ty) => {
+        sql_write!($ty, |d: Dialect, v: $ty| d.quote_literal(&v.to_string()))
+    };
+    ($ty:ty, $fmt:literal) => {
+        sql_write!($ty, |d: Dialect, v: $ty| d.quote_literal(&v.format($fmt).to_string()))
+    };
+}
+
+type SqlConvFn<R> = for<'r> fn(usize, &'r R, Dialect) -> Result<String>;
+
+pub struct SQL<DB: Source> {
+    phantom: PhantomData<DB>,
+}
+
+impl<DB: Source> Converter<DB> for SQL<DB> {
+    type ConvFn = SqlConvFn<DB::Row>;
+
+    fn convert(field: &Field, _opts: &Options) -> Self::ConvFn {
+        match field.kind {
+            FieldKind::INT8 => sql_write!(i8),
+            FieldKind::INT16 => sql_write!(i16),
+            FieldKind::INT32 => sql_write!(i32),
+            FieldKind::INT64 => sql_write!(i64),
+            FieldKind::UINT8 => sql_write!(u8),
+            FieldKind::UINT16 => sql_write!(u16),
+            FieldKind::UINT32 => sql_write!(u32),
+            FieldKind::UINT64 | FieldKind::BIT => sql_write!(u64),
+            FieldKind::FLOAT32 => sql_write!(f32, |d: Dialect, v: f32| d.float(v)),
+            FieldKind::FLOAT64 => sql_write!(f64, |d: Dialect, v: f64| d.float(v)),
+            FieldKind::STR => sql_write!(&str, |d: Dialect, v: &str| d.quote_literal(v)),
+            FieldKind::BOOL => sql_write!(bool, |_d, v: bool| if v { "TRUE" } else { "FALSE" }.into()),
+            FieldKind::DECIMAL(_) => sql_write!(Dec, |d: Dialect, v: Dec| match v {
+                Dec::Number(v) => v.to_string(),
+                Dec::Text(text) => d.quote_literal(&text),
+            }),
+            FieldKind::DATE => sql_quote!(NaiveDate, "%Y-%m-%d"),
+            FieldKind::TIME => sql_quote!(NaiveTime, "%H:%M:%S%.f"),
+            FieldKind::DATETIME => sql_quote!(NaiveDateTime, "%Y-%m-%d %H:%M:%S%.f"),
+            FieldKind::DATETIMETZ => sql_quote!(DateTime<Local>, "%Y-%m-%d %H:%M:%S%.f%:z"),
+            FieldKind::JSON => sql_quote!(JsonValue),
+            FieldKind::UUID => sql_quote!(Uuid),
+            FieldKind::BINARY => sql_write!(&[u8], |d: Dialect, v: &[u8]| d.binary(v)),
+            FieldKind::ARRAY(_)
+            | FieldKind::COMPOSITE(_)
+            | FieldKind::RANGE(_)
+            | FieldKind::INTERVAL
+            | FieldKind::NETWORK => sql_quote!(Nested),
+            FieldKind::UNKNOWN(_) => sql_quote!(Raw),
+        }
+    }
+
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let so = &opts.sql;
+        let dialect = Dialect::new::<DB>(so.dialect);
+        let table = so
+            .table
+            .clone()
+            .unwrap_or_else(|| output.as_ref().file_stem().unwrap_or_default().to_string_lossy().into_owned());
+        let mut sf = BufWriter::new(File::create(output)?);
+        let mut result = result.peekable();
+        if result.peek().is_some() {
+            let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+            let head = format!(
+                "INSERT INTO {} ({}) VALUES",
+                dialect.quote_table(&table),
+                columns.iter().map(|fld| dialect.quote_ident(&fld.name)).collect::<Vec<_>>().join(", ")
+            );
+            let batch_size = so.batch_size as usize;
+            let mut r = 0;
+            while let Some(rw) = result.next() {
+                let rw = rw?;
+                if r % batch_size == 0 {
+                    writeln!(sf, "{head}")?;
+                }
+                r += 1;
+                let values = convs
+                    .iter()
+                    .enumerate()
+                    .map(|(c, conv)| conv(columns[c].ordinal, &rw, dialect))
+                    .collect::<Result<Vec<_>>>()?;
+                let sep = if r % batch_size != 0 && result.peek().is_some() { ',' } else { ';' };
+                writeln!(sf, "({}){sep}", values.join(", "))?;
+            }
+        }
+        sf.flush()?;
+        Ok(())
+    }
+}
@@ -1,177 +1,1043 @@
-use anyhow::Result;
-use enum_map::{enum_map, Enum, EnumMap};
-use num_traits::ToPrimitive;
-use rust_xlsxwriter::{ColNum, Format, RowNum, Workbook, Worksheet};
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use enum_map::{Enum, EnumMap};
+use rust_xlsxwriter::{cell_autofit_width, ColNum, Color, Format, RowNum, Workbook, Worksheet};
+use rust_xlsxwriter::{
+    ConditionalFormat2ColorScale, ConditionalFormat3ColorScale, ConditionalFormatCell, ConditionalFormatCellRule,
+    ConditionalFormatDataBar, ConditionalFormatIconSet, ConditionalFormatIconType, ConditionalFormatText,
+    ConditionalFormatTextRule,
+};
+use rust_xlsxwriter::{Table, TableColumn, TableFunction, TableStyle};
+use serde_json::Map;
 use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
-use sqlx::types::{Decimal, JsonValue};
-use sqlx::{ColumnIndex, Database, Decode, Type, Row};
+use sqlx::types::Decimal;
+use sqlx::types::JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
 use std::marker::PhantomData;
-use std::path::Path;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Once;
+use uuid::Uuid;
 
-use super::{Field, Converter, FieldKind};
+use super::gfm::GFM;
+use super::{BigUint, Bits, Converter, Dec, Field, FieldKind, Nested, Options, Raw, Sheet};
+use crate::source::{Record, Source};
 
-#[derive(Enum)]
+#[derive(Args, Debug)]
+pub struct XlsxOptions {
+    /// Rows per XLSX worksheet, header included, before the result goes on in another one [default: Excel's 1048576]
+    #[arg(
+        long,
+        default_value_t = ROW_MAX,
+        hide_default_value = true,
+        value_parser = clap::value_parser!(u32).range(2..=ROW_MAX as i64)
+    )]
+    pub xlsx_max_rows: u32,
+
+    /// Where results longer than an XLSX worksheet go on
+    #[arg(long, value_enum, default_value_t = Split::Sheets)]
+    pub xlsx_split: Split,
+
+    /// JSON style file for XLSX output, overridden by the other options:
+    /// {"font": NAME, "font_size": SIZE, "header": {"bold", "italic", "color", "background"},
+    /// "formats": {KIND: FORMAT}, "columns": {COLUMN: {"format": FORMAT, "width": WIDTH, "conditional": [RULE]}}}
+    #[arg(long, value_name = "FILE")]
+    pub xlsx_style: Option<PathBuf>,
+
+    /// XLSX font name
+    #[arg(long)]
+    pub xlsx_font: Option<String>,
+
+    /// XLSX font size in points
+    #[arg(long)]
+    pub xlsx_font_size: Option<f64>,
+
+    /// XLSX number format of a kind of value, repeatable
+    /// (KIND: text, int, float, decimal, date, time, datetime; decimal defaults to the scale of each value)
+    #[arg(long, value_name = "KIND=FORMAT", value_parser = kind_format)]
+    pub xlsx_format: Vec<(XF, String)>,
+
+    /// XLSX number format of a column, whatever its kind, repeatable
+    #[arg(long, value_name = "COLUMN=FORMAT", value_parser = setting::<String>)]
+    pub xlsx_column_format: Vec<(String, String)>,
+
+    /// XLSX width of a column in characters instead of the fitted one, repeatable
+    #[arg(long, value_name = "COLUMN=WIDTH", value_parser = setting::<f64>)]
+    pub xlsx_column_width: Vec<(String, f64)>,
+
+    /// Write XLSX results as Excel tables of this style instead of ranges with an autofilter
+    /// (None, Light1-21, Medium1-28 or Dark1-11), keeping each worksheet in memory until the workbook is saved
+    #[arg(long, value_name = "STYLE", value_parser = table_style)]
+    pub xlsx_table: Option<TableLook>,
+
+    /// Add a totals row to XLSX tables
+    #[arg(long, requires = "xlsx_table")]
+    pub xlsx_totals: bool,
+
+    /// Aggregate of a kind of number in the totals row, repeatable (KIND: int, float, decimal) [default: sum]
+    #[arg(long, value_name = "KIND=FUNCTION", value_parser = kind_total, requires = "xlsx_totals")]
+    pub xlsx_total: Vec<(XF, Total)>,
+
+    /// Aggregate of a column in the totals row, whatever its kind, repeatable
+    #[arg(long, value_name = "COLUMN=FUNCTION", value_parser = column_total, requires = "xlsx_totals")]
+    pub xlsx_column_total: Vec<(String, Total)>,
+
+    /// XLSX conditional format of the values of a column, repeatable (RULE: color-scale, two-color-scale,
+    /// data-bar, icon-set[:ICONS], negative or text:TEXT, the last two highlighting the matching cells)
+    #[arg(long, value_name = "COLUMN=RULE", value_parser = column_rule)]
+    pub xlsx_conditional: Vec<(String, Rule)>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Split {
+    /// Next worksheets of the workbook: Sheet2, Sheet3… or "NAME (2)", "NAME (3)"…
+    Sheets,
+    /// Next workbook files: OUTPUT_2.xlsx, OUTPUT_3.xlsx…, numbered on across queries.
+    /// Every query starts on a worksheet of OUTPUT.xlsx
+    Files,
+}
+
+/// Cell formats, by kind of value
+#[derive(Enum, ValueEnum, Clone, Copy, PartialEq, Debug)]
 pub enum XF {
-    Bold,
+    #[value(skip)]
+    Header,
+    Text,
     Int,
-    Eur,
+    Float,
+    Decimal,
     Date,
     Time,
-    Stamp,
+    Datetime,
+}
+
+fn setting<T: FromStr>(value: &str) -> Result<(String, T), String> {
+    match value.split_once('=') {
+        Some((name, v)) if !name.is_empty() => {
+            Ok((name.to_string(), v.parse().map_err(|_| format!("invalid value \"{v}\""))?))
+        }
+        _ => Err("expected NAME=VALUE".into()),
+    }
+}
+
+fn kind(name: &str) -> Result<XF, String> {
+    <XF as ValueEnum>::from_str(name, true)
+        .map_err(|_| format!("unknown kind \"{name}\", expected text, int, float, decimal, date, time or datetime"))
+}
+
+fn kind_format(value: &str) -> Result<(XF, String), String> {
+    let (name, format) = value.split_once('=').ok_or("expected KIND=FORMAT")?;
+    Ok((kind(name)?, format.to_string()))
+}
+
+/// Excel table style
+#[derive(Clone, Copy)]
+pub struct TableLook(TableStyle);
+
+impl fmt::Debug for TableLook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+const TABLE_STYLES: [TableStyle; 61] = {
+    use TableStyle::*;
+    [
+        None, Light1, Light2, Light3, Light4, Light5, Light6, Light7, Light8, Light9, Light10, Light11, Light12,
+        Light13, Light14, Light15, Light16, Light17, Light18, Light19, Light20, Light21, Medium1, Medium2, Medium3,
+        Medium4, Medium5, Medium6, Medium7, Medium8, Medium9, Medium10, Medium11, Medium12, Medium13, Medium14,
+        Medium15, Medium16, Medium17, Medium18, Medium19, Medium20, Medium21, Medium22, Medium23, Medium24, Medium25,
+        Medium26, Medium27, Medium28, Dark1, Dark2, Dark3, Dark4, Dark5, Dark6, Dark7, Dark8, Dark9, Dark10, Dark11,
+    ]
+};
+
+fn table_style(name: &str) -> Result<TableLook, String> {
+    TABLE_STYLES
+        .into_iter()
+        .find(|style| style.to_string()["TableStyle".len()..].eq_ignore_ascii_case(name))
+        .map(TableLook)
+        .ok_or_else(|| "expected None, Light1-21, Medium1-28 or Dark1-11".into())
+}
+
+/// Aggregate of a column in the totals row of a table
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Total {
+    None,
+    Sum,
+    Average,
+    /// Non empty cells
+    Count,
+    /// Cells holding numbers
+    CountNumbers,
+    Max,
+    Min,
+    StdDev,
+    Var,
+}
+
+impl Total {
+    fn function(self) -> TableFunction {
+        match self {
+            Total::None => TableFunction::None,
+            Total::Sum => TableFunction::Sum,
+            Total::Average => TableFunction::Average,
+            Total::Count => TableFunction::Count,
+            Total::CountNumbers => TableFunction::CountNumbers,
+            Total::Max => TableFunction::Max,
+            Total::Min => TableFunction::Min,
+            Total::StdDev => TableFunction::StdDev,
+            Total::Var => TableFunction::Var,
+        }
+    }
+}
+
+fn kind_total(value: &str) -> Result<(XF, Total), String> {
+    let (name, total) = value.split_once('=').ok_or("expected KIND=FUNCTION")?;
+    match kind(name)? {
+        xf @ (XF::Int | XF::Float | XF::Decimal) => Ok((xf, Total::from_str(total, true)?)),
+        _ => Err(format!("no totals for kind \"{name}\", expected int, float or decimal")),
+    }
+}
+
+fn column_total(value: &str) -> Result<(String, Total), String> {
+    match value.split_once('=') {
+        Some((name, total)) if !name.is_empty() => Ok((name.to_string(), Total::from_str(total, true)?)),
+        _ => Err("expected COLUMN=FUNCTION".into()),
+    }
+}
+
+/// Conditional format of the values of a column
+#[derive(Clone, PartialEq, Debug)]
+pub enum Rule {
+    /// Red to yellow to green
+    ColorScale,
+    /// Yellow to green
+    TwoColorScale,
+    DataBar,
+    IconSet(Icons),
+    /// Highlight values below zero
+    Negative,
+    /// Highlight values containing the text
+    Text(String),
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Icons {
+    ThreeArrows,
+    ThreeArrowsGray,
+    ThreeFlags,
+    ThreeTrafficLights,
+    ThreeTrafficLightsWithRim,
+    ThreeSigns,
+    ThreeSymbolsCircled,
+    ThreeSymbols,
+    ThreeStars,
+    ThreeTriangles,
+    FourArrows,
+    FourArrowsGray,
+    FourRedToBlack,
+    FourHistograms,
+    FourTrafficLights,
+    FiveArrows,
+    FiveArrowsGray,
+    FiveHistograms,
+    FiveQuadrants,
+    FiveBoxes,
+}
+
+impl Icons {
+    fn icon_type(self) -> ConditionalFormatIconType {
+        match self {
+            Icons::ThreeArrows => ConditionalFormatIconType::ThreeArrows,
+            Icons::ThreeArrowsGray => ConditionalFormatIconType::ThreeArrowsGray,
+            Icons::ThreeFlags => ConditionalFormatIconType::ThreeFlags,
+            Icons::ThreeTrafficLights => ConditionalFormatIconType::ThreeTrafficLights,
+            Icons::ThreeTrafficLightsWithRim => ConditionalFormatIconType::ThreeTrafficLightsWithRim,
+            Icons::ThreeSigns => ConditionalFormatIconType::ThreeSigns,
+            Icons::ThreeSymbolsCircled => ConditionalFormatIconType::ThreeSymbolsCircled,
+            Icons::ThreeSymbols => ConditionalFormatIconType::ThreeSymbols,
+            Icons::ThreeStars => ConditionalFormatIconType::ThreeStars,
+            Icons::ThreeTriangles => ConditionalFormatIconType::ThreeTriangles,
+            Icons::FourArrows => ConditionalFormatIconType::FourArrows,
+            Icons::FourArrowsGray => ConditionalFormatIconType::FourArrowsGray,
+            Icons::FourRedToBlack => ConditionalFormatIconType::FourRedToBlack,
+            Icons::FourHistograms => ConditionalFormatIconType::FourHistograms,
+            Icons::FourTrafficLights => ConditionalFormatIconType::FourTrafficLights,
+            Icons::FiveArrows => ConditionalFormatIconType::FiveArrows,
+            Icons::FiveArrowsGray => ConditionalFormatIconType::FiveArrowsGray,
+            Icons::FiveHistograms => ConditionalFormatIconType::FiveHistograms,
+            Icons::FiveQuadrants => ConditionalFormatIconType::FiveQuadrants,
+            Icons::FiveBoxes => ConditionalFormatIconType::FiveBoxes,
+        }
+    }
+}
+
+impl Rule {
+    /// Apply to the cells of a column from `first` to `last` row
+    fn add(&self, ws: &mut Worksheet, first: RowNum, last: RowNum, c: ColNum) -> Result<()> {
+        // Excel's light red fill with dark red text
+        let highlight =
+            || Format::new().set_font_color(Color::RGB(0x9C0006)).set_background_color(Color::RGB(0xFFC7CE));
+        match self {
+            Rule::ColorScale => ws.add_conditional_format(first, c, last, c, &ConditionalFormat3ColorScale::new())?,
+            Rule::TwoColorScale => {
+                ws.add_conditional_format(first, c, last, c, &ConditionalFormat2ColorScale::new())?
+            }
+            Rule::DataBar => ws.add_conditional_format(first, c, last, c, &ConditionalFormatDataBar::new())?,
+            Rule::IconSet(icons) => ws.add_conditional_format(
+                first,
+                c,
+                last,
+                c,
+                &ConditionalFormatIconSet::new().set_icon_type(icons.icon_type()),
+            )?,
+            Rule::Negative => ws.add_conditional_format(
+                first,
+                c,
+                last,
+                c,
+                &ConditionalFormatCell::new().set_rule(ConditionalFormatCellRule::LessThan(0)).set_format(highlight()),
+            )?,
+            Rule::Text(text) => ws.add_conditional_format(
+                first,
+                c,
+                last,
+                c,
+                &ConditionalFormatText::new()
+                    .set_rule(ConditionalFormatTextRule::Contains(text.clone()))
+                    .set_format(highlight()),
+            )?,
+        };
+        Ok(())
+    }
+}
+
+fn rule(value: &str) -> Result<Rule, String> {
+    let (name, arg) = match value.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (value, None),
+    };
+    match (name, arg) {
+        ("color-scale", None) => Ok(Rule::ColorScale),
+        ("two-color-scale", None) => Ok(Rule::TwoColorScale),
+        ("data-bar", None) => Ok(Rule::DataBar),
+        ("icon-set", None) => Ok(Rule::IconSet(Icons::ThreeTrafficLights)),
+        ("icon-set", Some(icons)) => Ok(Rule::IconSet(Icons::from_str(icons, true)?)),
+        ("negative", None) => Ok(Rule::Negative),
+        ("text", Some(text)) => Ok(Rule::Text(text.to_string())),
+        _ => Err("expected color-scale, two-color-scale, data-bar, icon-set[:ICONS], negative or text:TEXT".into()),
+    }
+}
+
+fn column_rule(value: &str) -> Result<(String, Rule), String> {
+    match value.split_once('=') {
+        Some((name, v)) if !name.is_empty() => Ok((name.to_string(), rule(v)?)),
+        _ => Err("expected COLUMN=RULE".into()),
+    }
 }
 
 #[macro_export]
 macro_rules! xlsx_write {
     () => {
-        |_r, _c, _ws, _rw, _fm| Ok(())
+        |_r, _c, _ws, _rw, _i, _fm, _o| Ok(false)
     };
     ($ty:ty) => {
-        |r, c, ws, rw, _fm| {
-            ws.write(r, c, rw.get::<$ty, _>(c as usize))?;
-            Ok(())
+        |r, c, ws, rw, i, fm, _o| {
+            let Some(v) = rw.get::<$ty>(i)? else {
+                return Ok(false);
+            };
+            ws.write_with_format(r, c, v, &fm[XF::Text])?;
+            Ok(true)
         }
     };
     (Option<$ty:ty>, $fmt:path) => {
-        |r, c, ws, rw, fm| {
-            if let Some(v) = rw.get::<Option<$ty>, _>(c as usize) {
-                ws.write_with_format(r, c, &v, &fm[$fmt])?;
-            }
-            Ok(())
+        |r, c, ws, rw, i, fm, _o| {
+            let Some(v) = rw.get::<$ty>(i)? else {
+                return Ok(false);
+            };
+            ws.write_with_format(r, c, &v, &fm[$fmt])?;
+            Ok(true)
         }
     };
     ($ty:ty, $fmt:path) => {
-        |r, c, ws, rw, fm| {
-            ws.write_with_format(r, c, rw.get::<$ty, _>(c as usize), &fm[$fmt])?;
-            Ok(())
+        |r, c, ws, rw, i, fm, _o| {
+            let Some(v) = rw.get::<$ty>(i)? else {
+                return Ok(false);
+            };
+            ws.write_with_format(r, c, v, &fm[$fmt])?;
+            Ok(true)
         }
     };
 }
 
-type XlsxFmtMap = EnumMap<XF, Format>;
-type XlsxConvFn<'a, R> = fn(RowNum, ColNum, &mut Worksheet, &'a R, &XlsxFmtMap) -> Result<()>;
+/// Look of a workbook: the --xlsx-style file, then the other XLSX options over it
+pub struct Style {
+    font: Option<String>,
+    font_size: Option<f64>,
+    header: HeaderStyle,
+    /// Number formats replacing the default ones
+    formats: EnumMap<XF, Option<String>>,
+    columns: HashMap<String, ColumnStyle>,
+}
+
+struct HeaderStyle {
+    bold: bool,
+    italic: bool,
+    color: Option<Color>,
+    background: Option<Color>,
+}
+
+#[derive(Default)]
+struct ColumnStyle {
+    format: Option<String>,
+    width: Option<f64>,
+    conditional: Vec<Rule>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            font: None,
+            font_size: None,
+            header: HeaderStyle { bold: true, italic: false, color: None, background: None },
+            formats: EnumMap::default(),
+            columns: HashMap::new(),
+        }
+    }
+}
+
+impl Style {
+    fn new(opts: &XlsxOptions) -> Result<Self> {
+        let mut style = match &opts.xlsx_style {
+            Some(path) => fs::read_to_string(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Self::parse(&serde_json::from_str(&text)?))
+                .with_context(|| format!("XLSX style file {}", path.display()))?,
+            None => Self::default(),
+        };
+        style.font = opts.xlsx_font.clone().or(style.font);
+        style.font_size = opts.xlsx_font_size.or(style.font_size);
+        for (xf, format) in &opts.xlsx_format {
+            style.formats[*xf] = Some(format.clone());
+        }
+        for (name, format) in &opts.xlsx_column_format {
+            style.columns.entry(name.clone()).or_default().format = Some(format.clone());
+        }
+        for (name, width) in &opts.xlsx_column_width {
+            style.columns.entry(name.clone()).or_default().width = Some(*width);
+        }
+        for (name, rule) in &opts.xlsx_conditional {
+            style.columns.entry(name.clone()).or_default().conditional.push(rule.clone());
+        }
+        Ok(style)
+    }
+
+    fn parse(value: &JsonValue) -> Result<Self> {
+        let mut style = Self::default();
+        for (key, v) in object("style", value)? {
+            match key.as_str() {
+                "font" => style.font = Some(string(key, v)?),
+                "font_size" => style.font_size = Some(number(key, v)?),
+                "header" => {
+                    for (key, v) in object(key, v)? {
+                        match key.as_str() {
+                            "bold" => style.header.bold = boolean(key, v)?,
+                            "italic" => style.header.italic = boolean(key, v)?,
+                            "color" => style.header.color = Some(color(key, v)?),
+                            "background" => style.header.background = Some(color(key, v)?),
+                            _ => bail!("Unknown header style \"{key}\""),
+                        }
+                    }
+                }
+                "formats" => {
+                    for (name, v) in object(key, v)? {
+                        style.formats[kind(name).map_err(anyhow::Error::msg)?] = Some(string(name, v)?);
+                    }
+                }
+                "columns" => {
+                    for (name, v) in object(key, v)? {
+                        let column = style.columns.entry(name.clone()).or_default();
+                        for (key, v) in object(name, v)? {
+                            match key.as_str() {
+                                "format" => column.format = Some(string(key, v)?),
+                                "width" => column.width = Some(number(key, v)?),
+                                "conditional" => {
+                                    let rules = v.as_array();
+                                    for v in rules.with_context(|| format!("Style \"{key}\" must be an array"))? {
+                                        column.conditional.push(rule(&string(key, v)?).map_err(anyhow::Error::msg)?);
+                                    }
+                                }
+                                _ => bail!("Unknown column style \"{key}\""),
+                            }
+                        }
+                    }
+                }
+                _ => bail!("Unknown style \"{key}\""),
+            }
+        }
+        Ok(style)
+    }
+
+    /// Plain format in the chosen font
+    fn base(&self) -> Format {
+        let mut format = Format::new();
+        if let Some(font) = &self.font {
+            format = format.set_font_name(font);
+        }
+        if let Some(size) = self.font_size {
+            format = format.set_font_size(size);
+        }
+        format
+    }
+
+    /// Cell formats of a column, all of them with the number format of the column if it has one
+    fn formats(&self, fld: &Field) -> XlsxFmtMap {
+        let column = self.columns.get(&fld.name).and_then(|c| c.format.as_deref());
+        let custom = |xf: XF| column.or(self.formats[xf].as_deref());
+        let number = |xf: XF, default: &str| match custom(xf).unwrap_or(default) {
+            "" => self.base(),
+            format => self.base().set_num_format(format),
+        };
+        let mut header = self.base();
+        if self.header.bold {
+            header = header.set_bold();
+        }
+        if self.header.italic {
+            header = header.set_italic();
+        }
+        if let Some(color) = self.header.color {
+            header = header.set_font_color(color);
+        }
+        if let Some(color) = self.header.background {
+            header = header.set_background_color(color);
+        }
+        let decimal = match custom(XF::Decimal) {
+            Some(_) => Vec::new(),
+            None => (0..=Decimal::MAX_SCALE as usize)
+                .map(|scale| match scale {
+                    0 => number(XF::Decimal, "#,##0"),
+                    _ => number(XF::Decimal, &format!("#,##0.{}", "0".repeat(scale))),
+                })
+                .collect(),
+        };
+        let scale = match fld.kind {
+            FieldKind::DECIMAL(Some((_, scale))) => u32::try_from(scale).ok(),
+            _ => None,
+        };
+        XlsxFmtMap {
+            scale,
+            xf: EnumMap::from_fn(|xf| match xf {
+                XF::Header => header.clone(),
+                XF::Text => number(xf, ""),
+                XF::Int => number(xf, "#,##0"),
+                XF::Float => number(xf, "#,##0.00"),
+                XF::Decimal => number(xf, "#,##0"),
+                XF::Date => number(xf, "dd/mm/yyyy"),
+                XF::Time => number(xf, "hh:mm"),
+                XF::Datetime => number(xf, "dd/mm/yyyy hh:mm:ss"),
+            }),
+            decimal,
+        }
+    }
+
+    /// Width of a column in characters, if not fitted to its values
+    fn width(&self, column: &str) -> Option<f64> {
+        self.columns.get(column).and_then(|c| c.width)
+    }
 
-pub struct XLSX<DB: Database> {
+    /// Conditional formats of a column
+    fn conditional(&self, column: &str) -> &[Rule] {
+        self.columns.get(column).map_or(&[], |c| &c.conditional)
+    }
+
+    /// Column options must name a column of some sheet; style file columns that match none only get a warning
+    fn check(&self, names: &HashSet<String>, opts: &XlsxOptions) -> Result<()> {
+        let given = opts
+            .xlsx_column_format
+            .iter()
+            .map(|(name, _)| (name, "--xlsx-column-format"))
+            .chain(opts.xlsx_column_width.iter().map(|(name, _)| (name, "--xlsx-column-width")))
+            .chain(opts.xlsx_conditional.iter().map(|(name, _)| (name, "--xlsx-conditional")));
+        for (name, option) in given {
+            if !names.contains(name) {
+                bail!("Column \"{name}\" given to {option} is in no query result");
+            }
+        }
+        let mut unmatched = self.columns.keys().filter(|name| !names.contains(*name)).collect::<Vec<_>>();
+        unmatched.sort();
+        for name in unmatched {
+            eprintln!("Warning: column \"{name}\" of the XLSX style file is in no query result");
+        }
+        Ok(())
+    }
+}
+
+fn object<'v>(key: &str, v: &'v JsonValue) -> Result<&'v Map<String, JsonValue>> {
+    v.as_object().with_context(|| format!("Style \"{key}\" must be an object"))
+}
+
+fn string(key: &str, v: &JsonValue) -> Result<String> {
+    v.as_str().map(str::to_string).with_context(|| format!("Style \"{key}\" must be a string"))
+}
+
+fn number(key: &str, v: &JsonValue) -> Result<f64> {
+    v.as_f64().with_context(|| format!("Style \"{key}\" must be a number"))
+}
+
+fn boolean(key: &str, v: &JsonValue) -> Result<bool> {
+    v.as_bool().with_context(|| format!("Style \"{key}\" must be true or false"))
+}
+
+/// Color as "#RRGGBB"
+fn color(key: &str, v: &JsonValue) -> Result<Color> {
+    v.as_str()
+        .and_then(|v| v.strip_prefix('#'))
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .map(Color::RGB)
+        .with_context(|| format!("Style \"{key}\" must be a \"#RRGGBB\" color"))
+}
+
+/// Cell formats, plus one per number of decimal places for DECIMAL values unless a format is given for them
+pub struct XlsxFmtMap {
+    /// Declared scale of a DECIMAL column
+    scale: Option<u32>,
+    xf: EnumMap<XF, Format>,
+    decimal: Vec<Format>,
+}
+
+impl Index<XF> for XlsxFmtMap {
+    type Output = Format;
+
+    fn index(&self, xf: XF) -> &Format {
+        &self.xf[xf]
+    }
+}
+
+impl XlsxFmtMap {
+    /// Thousands separated number showing the declared decimal places of the column, or `scale` when it has none
+    fn decimal(&self, scale: u32) -> &Format {
+        let scale = self.scale.unwrap_or(scale);
+        self.decimal.as_slice().get(scale as usize).unwrap_or(&self.xf[XF::Decimal])
+    }
+}
+
+/// Writes a cell, returning false for NULL values which are left to the caller
+type XlsxConvFn<R> = for<'r> fn(RowNum, ColNum, &mut Worksheet, &'r R, usize, &XlsxFmtMap, &Options) -> Result<bool>;
+
+/// Rows of an Excel worksheet
+const ROW_MAX: RowNum = 1_048_576;
+
+/// Longest Excel worksheet name
+const NAME_MAX: usize = 31;
+
+/// Extra width of header cells for the autofilter dropdown
+const AUTOFILTER_WIDTH: u32 = 16;
+
+/// Largest integer Excel numbers (f64) hold exactly
+const MAX_EXACT: u64 = 1 << 53;
+
+static PRECISION_WARNING: Once = Once::new();
+static SIGNED_PRECISION_WARNING: Once = Once::new();
+
+pub struct XLSX<DB: Source> {
     phantom: PhantomData<DB>,
 }
 
-impl<'a, DB: Database> Converter<'a, DB> for XLSX<DB> {
-    type ConvFn = XlsxConvFn<'a, DB::Row>;
-
-    fn convert(field: &Field) -> Self::ConvFn
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>,
-    {
+impl<DB: Source> Converter<DB> for XLSX<DB> {
+    type ConvFn = XlsxConvFn<DB::Row>;
+
+    fn convert(field: &Field, opts: &Options) -> Self::ConvFn {
         match field.kind {
             FieldKind::INT8 => xlsx_write!(i8, XF::Int),
             FieldKind::INT16 => xlsx_write!(i16, XF::Int),
             FieldKind::INT32 => xlsx_write!(i32, XF::Int),
-            FieldKind::INT64 => |r, c, ws, rw, fm| {
-                ws.write_with_format(r, c, rw.get::<i64, _>(c as usize) as f64, &fm[XF::Int])?;
-                Ok(())
+            FieldKind::INT64 => |r, c, ws, rw, i, fm, _o| {
+                let Some(v) = rw.get::<i64>(i)? else {
+                    return Ok(false);
+                };
+                if v.unsigned_abs() > MAX_EXACT {
+                    SIGNED_PRECISION_WARNING
+                        .call_once(|| eprintln!("Warning: i64 values beyond ±2^53 rounded in XLSX"));
+                }
+                ws.write_with_format(r, c, v as f64, &fm[XF::Int])?;
+                Ok(true)
             },
-            FieldKind::UINT8 => todo!(),
-            FieldKind::UINT16 => todo!(),
-            FieldKind::UINT32 => todo!(),
-            FieldKind::UINT64 => todo!(),
-            FieldKind::FLOAT32 => xlsx_write!(f32, XF::Eur),
-            FieldKind::FLOAT64 => xlsx_write!(f64, XF::Eur),
+            FieldKind::UINT8 => xlsx_write!(u8, XF::Int),
+            FieldKind::UINT16 => xlsx_write!(u16, XF::Int),
+            FieldKind::UINT32 => xlsx_write!(u32, XF::Int),
+            FieldKind::BIT if opts.bit == Bits::Text => |r, c, ws, rw, i, fm, _o| {
+                let Some(v) = rw.get::<u64>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, format!("{v:b}"), &fm[XF::Text])?;
+                Ok(true)
+            },
+            FieldKind::UINT64 | FieldKind::BIT => match opts.big_uint {
+                BigUint::Number => |r, c, ws, rw, i, fm, _o| {
+                    let Some(v) = rw.get::<u64>(i)? else {
+                        return Ok(false);
+                    };
+                    if v > MAX_EXACT {
+                        PRECISION_WARNING
+                            .call_once(|| eprintln!("Warning: u64 values above 2^53 rounded in XLSX, see --big-uint"));
+                    }
+                    ws.write_with_format(r, c, v, &fm[XF::Int])?;
+                    Ok(true)
+                },
+                BigUint::Text => |r, c, ws, rw, i, fm, _o| {
+                    let Some(v) = rw.get::<u64>(i)? else {
+                        return Ok(false);
+                    };
+                    ws.write_with_format(r, c, v.to_string(), &fm[XF::Text])?;
+                    Ok(true)
+                },
+            },
+            FieldKind::FLOAT32 => xlsx_write!(f32, XF::Float),
+            FieldKind::FLOAT64 => xlsx_write!(f64, XF::Float),
             FieldKind::STR => xlsx_write!(&str),
             FieldKind::BOOL => xlsx_write!(bool),
-            FieldKind::DECIMAL => |r, c, ws, rw, fm| {
-                ws.write_with_format(r, c, rw.get::<Decimal, _>(c as usize).to_f64().unwrap(), &fm[XF::Eur])?;
-                Ok(())
+            // Excel numbers are doubles, the format at least shows the decimal places of the source
+            FieldKind::DECIMAL(_) => |r, c, ws, rw, i, fm, _o| {
+                match rw.get::<Dec>(i)? {
+                    Some(Dec::Number(v)) => ws.write_with_format(r, c, f64::try_from(v)?, fm.decimal(v.scale()))?,
+                    Some(Dec::Text(text)) => ws.write_with_format(r, c, text, &fm[XF::Text])?,
+                    None => return Ok(false),
+                };
+                Ok(true)
             },
             FieldKind::DATE => xlsx_write!(Option<NaiveDate>, XF::Date),
             FieldKind::TIME => xlsx_write!(Option<NaiveTime>, XF::Time),
-            FieldKind::DATETIME => xlsx_write!(Option<NaiveDateTime>, XF::Stamp),
-            FieldKind::DATETIMETZ => |r, c, ws, rw, fm| {
-                if let Some(v) = rw.get::<Option<DateTime<Local>>, _>(c as usize) {
-                    ws.write_with_format(r, c, &v.naive_local(), &fm[XF::Stamp])?;
-                }
-                Ok(())
+            FieldKind::DATETIME => xlsx_write!(Option<NaiveDateTime>, XF::Datetime),
+            // Excel dates have no time zone, write the local time of the chosen one
+            FieldKind::DATETIMETZ => |r, c, ws, rw, i, fm, o| {
+                let Some(v) = rw.get::<DateTime<Local>>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, &o.timezone.apply(v).naive_local(), &fm[XF::Datetime])?;
+                Ok(true)
             },
-            FieldKind::JSON => |r, c, ws, rw, _fm| {
-                ws.write(r, c, rw.get::<JsonValue, _>(c as usize).to_string())?;
-                Ok(())
+            FieldKind::JSON => |r, c, ws, rw, i, fm, _o| {
+                let Some(v) = rw.get::<JsonValue>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, v.to_string(), &fm[XF::Text])?;
+                Ok(true)
             },
-            FieldKind::UNKNOWN(_) => todo!(),
-        }
-    }
-
-    fn write(result: &[DB::Row], output: impl AsRef<Path>) -> Result<()>
-    where
-        DB: Database,
-        for<'b> i8: Decode<'b, DB> + Type<DB>,
-        for<'b> i16: Decode<'b, DB> + Type<DB>,
-        for<'b> i32: Decode<'b, DB> + Type<DB>,
-        for<'b> i64: Decode<'b, DB> + Type<DB>,
-        //for<'b> u8: Decode<'b, DB> + Type<DB>,
-        //for<'b> u16: Decode<'b, DB> + Type<DB>,
-        //for<'b> u32: Decode<'b, DB> + Type<DB>,
-        //for<'b> u64: Decode<'b, DB> + Type<DB>,
-        for<'b> f32: Decode<'b, DB> + Type<DB>,
-        for<'b> f64: Decode<'b, DB> + Type<DB>,
-        for<'b> &'b str: Decode<'b, DB> + Type<DB>,
-        for<'b> bool: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDate: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveDateTime: Decode<'b, DB> + Type<DB>,
-        for<'b> NaiveTime: Decode<'b, DB> + Type<DB>,
-        for<'b> DateTime<Local>: Decode<'b, DB> + Type<DB>,
-        for<'b> Decimal: Decode<'b, DB> + Type<DB>,
-        for<'b> JsonValue: Decode<'b, DB> + Type<DB>,
-        usize: ColumnIndex<DB::Row>,
-        for<'b> &'b DB::Column: Into<Field>,
-    {
-        let xf = enum_map! {
-            XF::Bold => Format::new().set_bold(),
-            XF::Int => Format::new().set_num_format("#,##0"),
-            XF::Eur => Format::new().set_num_format("#,##0.00"),
-            XF::Date => Format::new().set_num_format("dd/mm/yyyy"),
-            XF::Time => Format::new().set_num_format("hh:mm"),
-            XF::Stamp => Format::new().set_num_format("dd/mm/yyyy hh:mm:ss"),
-        };
-        let mut wb = Workbook::new();
-        let ws = wb.add_worksheet();
+            FieldKind::UUID => |r, c, ws, rw, i, fm, _o| {
+                let Some(v) = rw.get::<Uuid>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, v.to_string(), &fm[XF::Text])?;
+                Ok(true)
+            },
+            FieldKind::BINARY => |r, c, ws, rw, i, fm, o| {
+                let Some(v) = rw.get::<&[u8]>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, o.binary.encode(v), &fm[XF::Text])?;
+                Ok(true)
+            },
+            FieldKind::ARRAY(_)
+            | FieldKind::COMPOSITE(_)
+            | FieldKind::RANGE(_)
+            | FieldKind::INTERVAL
+            | FieldKind::NETWORK => |r, c, ws, rw, i, fm, o| {
+                let Some(v) = rw.get::<Nested>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, v.joined(o.timezone), &fm[XF::Text])?;
+                Ok(true)
+            },
+            FieldKind::UNKNOWN(_) => |r, c, ws, rw, i, fm, o| {
+                let Some(v) = rw.get::<Raw>(i)? else {
+                    return Ok(false);
+                };
+                ws.write_with_format(r, c, v.encode(o.binary), &fm[XF::Text])?;
+                Ok(true)
+            },
+        }
+    }
+
+    fn write(
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let style = Style::new(&opts.xlsx)?;
+        let mut book = Book::new(output.as_ref(), &opts.xlsx);
+        Self::sheet(&mut book, None, columns, result, &style, opts)?;
+        style.check(&columns.iter().map(|fld| fld.name.clone()).collect(), &opts.xlsx)?;
+        book.save()
+    }
+
+    fn companions(output: &Path, opts: &Options) -> Vec<PathBuf> {
+        match opts.xlsx.xlsx_split {
+            Split::Files => (2..).map(|n| numbered(output, n)).take_while(|path| path.exists()).collect(),
+            Split::Sheets => Vec::new(),
+        }
+    }
+
+    fn write_sheets(
+        sheets: impl ExactSizeIterator<Item = Result<Sheet<DB::Row>>>,
+        output: impl AsRef<Path>,
+        opts: &Options,
+    ) -> Result<()> {
+        let style = Style::new(&opts.xlsx)?;
+        let mut book = Book::new(output.as_ref(), &opts.xlsx);
+        let mut names = HashSet::new();
+        for sheet in sheets {
+            let sheet = sheet?;
+            names.extend(sheet.columns.iter().map(|fld| fld.name.clone()));
+            Self::sheet(&mut book, sheet.name.as_deref(), &sheet.columns, sheet.rows, &style, opts)?;
+        }
+        style.check(&names, &opts.xlsx)?;
+        book.save()
+    }
+}
+
+impl<DB: Source> XLSX<DB> {
+    /// Fill worksheets with a result: header row frozen at the top, autofilter and fitted column widths,
+    /// going on in another worksheet (or workbook) each time one is full
+    fn sheet(
+        book: &mut Book,
+        name: Option<&str>,
+        columns: &[Field],
+        result: impl Iterator<Item = Result<DB::Row>>,
+        style: &Style,
+        opts: &Options,
+    ) -> Result<()> {
+        let convs = columns.iter().map(|fld| Self::convert(fld, opts)).collect::<Vec<_>>();
+        let fms = columns.iter().map(|fld| style.formats(fld)).collect::<Vec<_>>();
+        // Worksheet::autofit only sees the current row in constant memory mode, measure the text rendering instead
+        let texts = columns.iter().map(|fld| GFM::<DB>::convert(fld, opts)).collect::<Vec<_>>();
+        let headers = columns.iter().map(|fld| cell_autofit_width(&fld.name) + AUTOFILTER_WIDTH).collect::<Vec<_>>();
+        let table = Self::table(columns, &fms, opts);
+        // Rows under the header, leaving room for the totals row
+        let capacity = opts.xlsx.xlsx_max_rows - 1 - opts.xlsx.xlsx_totals as RowNum;
+        if capacity == 0 {
+            bail!("--xlsx-max-rows leaves no room for rows between the header and the totals row");
+        }
+        let mut part = 1;
+        let mut ws = Self::header(book.add(name, part)?, columns, &fms)?;
+        let mut widths = headers.clone();
+        let mut r = 0;
+        for rw in result {
+            let rw = rw?;
+            if r == capacity {
+                Self::finish(ws, columns, r, widths, style, table.as_ref())?;
+                part += 1;
+                ws = Self::header(book.add(name, part)?, columns, &fms)?;
+                widths = headers.clone();
+                r = 0;
+            }
+            r += 1;
+            for (c, conv) in convs.iter().enumerate() {
+                // NULL cells stay empty unless a placeholder is given
+                if !conv(r, c as ColNum, ws, &rw, columns[c].ordinal, &fms[c], opts)? && !opts.null.is_empty() {
+                    ws.write_with_format(r, c as ColNum, &opts.null, &fms[c][XF::Text])?;
+                }
+            }
+            for (c, text) in texts.iter().enumerate() {
+                let text = text(columns[c].ordinal, &rw, opts)?;
+                widths[c] = widths[c].max(cell_autofit_width(text.as_deref().unwrap_or(&opts.null)));
+            }
+        }
+        Self::finish(ws, columns, r, widths, style, table.as_ref())
+    }
+
+    /// Excel table of a result, with its totals row if asked, if results go to tables
+    fn table(columns: &[Field], fms: &[XlsxFmtMap], opts: &Options) -> Option<Table> {
+        let xo = &opts.xlsx;
+        let TableLook(style) = xo.xlsx_table?;
+        let columns = columns
+            .iter()
+            .enumerate()
+            .map(|(c, fld)| {
+                let column = TableColumn::new().set_header(&fld.name).set_header_format(&fms[c][XF::Header]);
+                if !xo.xlsx_totals {
+                    return column;
+                }
+                let kind = Self::number(&fld.kind, opts);
+                let total = match xo.xlsx_column_total.iter().rfind(|(name, _)| *name == fld.name) {
+                    Some((_, total)) => *total,
+                    None => kind.map_or(Total::None, |kind| {
+                        xo.xlsx_total.iter().rfind(|(xf, _)| *xf == kind).map_or(Total::Sum, |(_, total)| *total)
+                    }),
+                };
+                // The format of a table column also goes to its data cells, only give the one they already have
+                let format = match kind {
+                    Some(XF::Decimal) => fms[c].scale.map(|scale| fms[c].decimal(scale)),
+                    None => None,
+                    Some(xf) => Some(&fms[c][xf]),
+                };
+                let column = match format {
+                    Some(format) => column.set_format(format),
+                    None => column,
+                };
+                match total {
+                    Total::None if c == 0 => column.set_total_label("Total"),
+                    total => column.set_total_function(total.function()),
+                }
+            })
+            .collect::<Vec<_>>();
+        Some(Table::new().set_style(style).set_columns(&columns).set_total_row(xo.xlsx_totals))
+    }
+
+    /// Kind of number of a field in XLSX, if written as a number
+    fn number(kind: &FieldKind, opts: &Options) -> Option<XF> {
+        match kind {
+            FieldKind::INT8
+            | FieldKind::INT16
+            | FieldKind::INT32
+            | FieldKind::INT64
+            | FieldKind::UINT8
+            | FieldKind::UINT16
+            | FieldKind::UINT32 => Some(XF::Int),
+            FieldKind::BIT if opts.bit == Bits::Text => None,
+            FieldKind::UINT64 | FieldKind::BIT if opts.big_uint == BigUint::Text => None,
+            FieldKind::UINT64 | FieldKind::BIT => Some(XF::Int),
+            FieldKind::FLOAT32 | FieldKind::FLOAT64 => Some(XF::Float),
+            FieldKind::DECIMAL(_) => Some(XF::Decimal),
+            _ => None,
+        }
+    }
+
+    /// Header row, frozen at the top
+    fn header<'w>(ws: &'w mut Worksheet, columns: &[Field], fms: &[XlsxFmtMap]) -> Result<&'w mut Worksheet> {
         ws.set_freeze_panes(1, 0)?;
-        if !result.is_empty() {
-            let r = 0;
-            let columns: Vec<Field> = result[0].columns().iter().map(|c| c.into()).collect();
-            let convs = columns
-                .iter()
-                .enumerate()
-                .inspect(|(c, fld)| {
-                    ws.write_with_format(r, *c as ColNum, &fld.name, &xf[XF::Bold]).unwrap();
-                })
-                .map(|(_c, fld)| Self::convert(fld))
-                .collect::<Vec<_>>();
-            for (r, rw) in result.iter().enumerate() {
-                for (c, conv) in convs.iter().enumerate() {
-                    conv((r + 1) as RowNum, c as ColNum, ws, rw, &xf)?;
+        for (c, fld) in columns.iter().enumerate() {
+            ws.write_with_format(0, c as ColNum, &fld.name, &fms[c][XF::Header])?;
+        }
+        Ok(ws)
+    }
+
+    /// Table or autofilter over the `rows` written below the header, conditional formats of their values,
+    /// and fitted column widths unless fixed
+    fn finish(
+        ws: &mut Worksheet,
+        columns: &[Field],
+        rows: RowNum,
+        widths: Vec<u32>,
+        style: &Style,
+        table: Option<&Table>,
+    ) -> Result<()> {
+        match table {
+            _ if columns.is_empty() => (),
+            // Tables need a data row, even an empty one, and take the totals row below it
+            Some(table) => {
+                let last = rows.max(1) + table.has_total_row() as RowNum;
+                ws.add_table(0, 0, last, (columns.len() as u16) - 1, table)?;
+            }
+            None => {
+                ws.autofilter(0, 0, rows, (columns.len() as u16) - 1)?;
+            }
+        }
+        for (c, fld) in columns.iter().enumerate().filter(|_| rows > 0) {
+            for rule in style.conditional(&fld.name) {
+                rule.add(ws, 1, rows, c as ColNum)?;
+            }
+        }
+        for (c, width) in widths.into_iter().enumerate() {
+            match style.width(&columns[c].name) {
+                Some(width) => ws.set_column_width(c as ColNum, width)?,
+                None => ws.set_column_autofit_width(c as ColNum, width)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Workbook being written, saved and replaced by the next file when results split into files
+struct Book<'a> {
+    /// Workbook of the output file, which every result starts in
+    wb: Workbook,
+    output: &'a Path,
+    split: Split,
+    /// Tables are added once their rows are written, after their header left memory in constant memory mode
+    constant_memory: bool,
+    /// Workbook file the current result goes on in when split into files, with its number
+    overflow: Option<(Workbook, usize)>,
+    /// Number of workbook files so far
+    files: usize,
+}
+
+/// Name of the `n`th output file, numbered from the second one on
+fn numbered(output: &Path, n: usize) -> PathBuf {
+    match n {
+        1 => output.to_path_buf(),
+        n => {
+            let mut name = output.file_stem().unwrap_or_default().to_os_string();
+            name.push(format!("_{n}"));
+            if let Some(ext) = output.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+            output.with_file_name(name)
+        }
+    }
+}
+
+impl<'a> Book<'a> {
+    fn new(output: &'a Path, opts: &XlsxOptions) -> Self {
+        Book {
+            wb: Workbook::new(),
+            output,
+            split: opts.xlsx_split,
+            constant_memory: opts.xlsx_table.is_none(),
+            overflow: None,
+            files: 1,
+        }
+    }
+
+    /// Worksheet for the `part`th piece of a result (from 1), named after the query if it has a name
+    fn add(&mut self, name: Option<&str>, part: usize) -> Result<&mut Worksheet> {
+        let wb = match self.split {
+            Split::Sheets => &mut self.wb,
+            // the first piece goes back to the output file, the others to a new file each
+            Split::Files => {
+                if let Some((mut wb, n)) = self.overflow.take() {
+                    wb.save(self.path(n))?;
+                }
+                match part {
+                    1 => &mut self.wb,
+                    _ => {
+                        self.files += 1;
+                        &mut self.overflow.insert((Workbook::new(), self.files)).0
+                    }
                 }
             }
-            ws.autofilter(0, 0, (result.len() as u32) - 1, (columns.len() as u16) - 1)?;
-            ws.autofit();
-            wb.save(output)?;
+        };
+        let ws = match self.constant_memory {
+            true => wb.add_worksheet_with_constant_memory(),
+            false => wb.add_worksheet(),
+        };
+        match name {
+            Some(name) if part == 1 || self.split == Split::Files => ws.set_name(name)?,
+            // Shorten the name so that the suffix fits
+            Some(name) => {
+                let suffix = format!(" ({part})");
+                ws.set_name(name.chars().take(NAME_MAX - suffix.len()).chain(suffix.chars()).collect::<String>())?
+            }
+            // Default names follow the position: Sheet1, Sheet2…
+            None => ws,
+        };
+        Ok(ws)
+    }
+
+    fn path(&self, n: usize) -> PathBuf {
+        numbered(self.output, n)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if let Some((mut wb, n)) = self.overflow.take() {
+            wb.save(self.path(n))?;
         }
+        self.wb.save(self.output)?;
         Ok(())
     }
 }
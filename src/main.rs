@@ -1,15 +1,27 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
+use conv::arrow::ARROW;
+use conv::copy::COPY;
+use conv::csv::CSV;
+use conv::gfm::GFM;
 use conv::json::JSON;
+use conv::sql::SQL;
 use conv::xlsx::XLSX;
-use conv::gfm::GFM;
-use conv::Converter;
-use sqlx::{Connection, Database, Executor, IntoArguments, MySql, Postgres};
+use conv::{Converter, Options, Sheet};
+use source::mssql::Mssql;
+use source::Source;
+use sqlx::{MySql, Postgres, Sqlite};
 use std::env::var;
+use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::runtime::Handle;
+use tokio::task::block_in_place;
 use url::Url;
 
 pub mod conv;
+pub mod source;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -22,19 +34,19 @@ struct Args {
     output: PathBuf,
 
     /// SQL query to execute
-    #[arg()]
-    query: String,
-}
+    #[arg(required_unless_present_any = ["queries", "query_file"], conflicts_with_all = ["queries", "query_file"])]
+    query: Option<String>,
+
+    /// Named query, repeatable: several of them go to the worksheets of an XLSX workbook, named after them
+    #[arg(long = "query", value_name = "NAME=SQL", value_parser = named_query)]
+    queries: Vec<(String, String)>,
 
-async fn db_fetch<'a, DB>(db_url: &'a Url, sql: &'a str) -> Result<Vec<DB::Row>>
-where
-    DB: Database,
-    DB::Arguments<'a>: IntoArguments<'a, DB>,
-    for<'b> &'b mut DB::Connection: Executor<'b, Database = DB>,
-{
-    let mut db = DB::Connection::connect(db_url.as_str()).await?;
-    let result = sqlx::query(sql).fetch_all(&mut db).await?;
-    Ok(result)
+    /// File of named queries, each one after a `-- name: NAME` line, run after those of --query
+    #[arg(long)]
+    query_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    options: Options,
 }
 
 // Thanks to DanielKeep
@@ -63,9 +75,7 @@ macro_rules! matcher {
             {
                 $($arms)*
                 $(
-                    ($str1_head, $str2) => {
-                        $typ2::<$typ1_head>::write(&db_fetch::<$typ1_head>($params.db_url, $params.query).await?, $params.output)?;
-                    }
+                    ($str1_head, $str2) => export::<$typ1_head, $typ2<$typ1_head>>(&$params)?,
                 )*
             };
             $($str1_tail => $typ1_tail),*;
@@ -88,15 +98,89 @@ macro_rules! matcher {
 
 struct Params<'a> {
     db_url: &'a Url,
-    query: &'a str,
+    /// Queries by sheet name, `None` for the positional one
+    queries: &'a [(Option<String>, String)],
     output: &'a Path,
     format: &'a str,
+    options: &'a Options,
+}
+
+fn named_query(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, sql)) if !name.trim().is_empty() => Ok((name.trim().to_string(), sql.to_string())),
+        _ => Err("expected NAME=SQL".into()),
+    }
+}
+
+/// Queries of a query file, each one following its `-- name: NAME` line
+fn query_file(text: &str) -> Result<Vec<(Option<String>, String)>> {
+    let mut queries: Vec<(Option<String>, String)> = Vec::new();
+    for line in text.lines() {
+        match line.trim().strip_prefix("--").and_then(|v| v.trim().strip_prefix("name:")) {
+            Some(name) => queries.push((Some(name.trim().to_string()), String::new())),
+            None => match queries.last_mut() {
+                Some((_, sql)) => {
+                    sql.push_str(line);
+                    sql.push('\n');
+                }
+                None if line.trim().is_empty() || line.trim().starts_with("--") => (),
+                None => bail!("Query file text before the first \"-- name:\" line"),
+            },
+        }
+    }
+    Ok(queries)
+}
+
+/// Last change of a file, `None` when there is none
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Run the queries one after the other, handing each result to the converter as soon as it starts coming.
+/// The output file and the files next to it the converter got to write are removed when the export fails
+fn export<DB: Source + 'static, C: Converter<DB>>(params: &Params) -> Result<()> {
+    let files = || iter::once(params.output.to_path_buf()).chain(C::companions(params.output, params.options));
+    let before = files().map(|path| (modified(&path), path)).collect::<Vec<_>>();
+    let result = block_in_place(|| {
+        let handle = Handle::current();
+        let mut unmatched = params.options.uuid.iter().collect::<Vec<_>>();
+        let last = params.queries.len() - 1;
+        let sheets = params.queries.iter().enumerate().map(|(q, (name, query))| {
+            let (columns, rows) = handle.block_on(source::stream::<DB>(params.db_url, query))?;
+            // --uuid columns may be missing from some results, not from all of them
+            unmatched.retain(|uuid| !columns.iter().any(|fld| &fld.name == *uuid));
+            if let (true, Some(uuid)) = (q == last, unmatched.first()) {
+                bail!("Column \"{uuid}\" given to --uuid is in no query result");
+            }
+            let columns = conv::resolve(columns, params.options)?;
+            Ok(Sheet { name: name.clone(), columns, rows })
+        });
+        C::write_sheets(sheets, params.output, params.options)
+    });
+    if result.is_err() {
+        for path in files() {
+            let before = before.iter().find(|(_, p)| *p == path).and_then(|(time, _)| *time);
+            if modified(&path).is_some_and(|after| Some(after) != before) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+    result
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    let mut queries = args.queries.into_iter().map(|(name, sql)| (Some(name), sql)).collect::<Vec<_>>();
+    if let Some(path) = &args.query_file {
+        queries.extend(query_file(&fs::read_to_string(path)?)?);
+    }
+    queries.extend(args.query.map(|sql| (None, sql)));
+    if queries.is_empty() {
+        bail!("No query to run, the query file has no \"-- name:\" line");
+    }
+
     let params = Params {
         db_url: &Url::parse(
             args.url
@@ -104,20 +188,33 @@ async fn main() -> Result<()> {
                 .as_ref(),
         )
         .expect("Invalid url"),
-        query: &args.query,
+        queries: &queries,
         output: &args.output,
         format: args.output.extension().unwrap().to_str().unwrap(),
+        options: &args.options,
     };
 
     matcher!(
         params
         :
         "mysql" => MySql,
-        "postgres" => Postgres
+        "postgres" => Postgres,
+        "sqlite" => Sqlite,
+        "mssql" => Mssql,
+        "sqlserver" => Mssql
         ;
         "json" => JSON,
+        "jsonl" => JSON,
+        "ndjson" => JSON,
         "xlsx" => XLSX,
-        "gfm" => GFM
+        "gfm" => GFM,
+        "csv" => CSV,
+        "tsv" => CSV,
+        "sql" => SQL,
+        "copy" => COPY,
+        "arrow" => ARROW,
+        "arrows" => ARROW,
+        "parquet" => ARROW
         ;
     );
 
@@ -0,0 +1,470 @@
+pub mod mssql;
+
+use anyhow::{anyhow, Result};
+use futures_util::TryStreamExt;
+use libsqlite3_sys::{
+    sqlite3_column_count, sqlite3_column_decltype, sqlite3_column_name, sqlite3_finalize, sqlite3_prepare_v2, SQLITE_OK,
+};
+use sqlx::error::BoxDynError;
+use sqlx::mysql::{MySql, MySqlConnection, MySqlRow};
+use sqlx::postgres::{PgConnection, PgRow, Postgres};
+use sqlx::sqlite::{Sqlite, SqliteConnection, SqliteRow};
+use sqlx::types::chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::types::JsonValue;
+use sqlx::{
+    Column, ColumnIndex, Connection, Database, Decode, Executor, IntoArguments, Row, Statement, Type, TypeInfo,
+    ValueRef,
+};
+use std::ffi::{c_char, CStr, CString};
+use std::future::Future;
+use std::ptr;
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+use uuid::Uuid;
+
+use crate::conv::{sqlite_affinity, Dec, Field, FieldKind, Nested, Raw};
+
+/// Typed access to a cell of a result row, `None` for NULL and an error naming the column when it does not decode
+pub trait Cell<'r, T> {
+    fn cell(&'r self, c: usize) -> Result<Option<T>>;
+}
+
+/// Row accessor used by converters: `rw.get::<i32>(c)?`
+pub trait Record {
+    fn get<'r, T>(&'r self, c: usize) -> Result<Option<T>>
+    where
+        Self: Cell<'r, T>,
+    {
+        self.cell(c)
+    }
+}
+
+impl<R> Record for R {}
+
+/// Database driver the converters can read from
+pub trait Source {
+    type Row: Send
+        + 'static
+        + for<'r> Cell<'r, i8>
+        + for<'r> Cell<'r, i16>
+        + for<'r> Cell<'r, i32>
+        + for<'r> Cell<'r, i64>
+        + for<'r> Cell<'r, u8>
+        + for<'r> Cell<'r, u16>
+        + for<'r> Cell<'r, u32>
+        + for<'r> Cell<'r, u64>
+        + for<'r> Cell<'r, f32>
+        + for<'r> Cell<'r, f64>
+        + for<'r> Cell<'r, &'r str>
+        + for<'r> Cell<'r, &'r [u8]>
+        + for<'r> Cell<'r, bool>
+        + for<'r> Cell<'r, NaiveDate>
+        + for<'r> Cell<'r, NaiveDateTime>
+        + for<'r> Cell<'r, NaiveTime>
+        + for<'r> Cell<'r, DateTime<Local>>
+        + for<'r> Cell<'r, Dec>
+        + for<'r> Cell<'r, JsonValue>
+        + for<'r> Cell<'r, Uuid>
+        + for<'r> Cell<'r, Nested>
+        + for<'r> Cell<'r, Raw>;
+
+    /// Driver name, as reported by SQLx `Database::NAME`
+    const NAME: &'static str;
+
+    /// Run the query, sending the result columns first and then every row
+    fn fetch(url: &Url, query: &str, tx: &mut Sender<Self::Row>) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Rows queued between the database and the converter
+const ROW_BUFFER: usize = 1024;
+
+/// Rows held back at most while looking for the type of columns without a declared one
+const LOOKAHEAD: usize = 1000;
+
+/// Sending half of a query result, used by `Source::fetch`
+pub struct Sender<R> {
+    columns: Option<oneshot::Sender<Vec<Field>>>,
+    rows: mpsc::Sender<Result<R>>,
+}
+
+impl<R> Sender<R> {
+    pub fn columns(&mut self, columns: Vec<Field>) {
+        if let Some(tx) = self.columns.take() {
+            let _ = tx.send(columns);
+        }
+    }
+
+    pub async fn row(&self, row: R) -> Result<()> {
+        self.rows.send(Ok(row)).await.map_err(|_| anyhow!("Output closed while fetching rows"))
+    }
+}
+
+/// Receiving half of a query result, blocks until the next row is fetched
+pub struct Rows<R>(mpsc::Receiver<Result<R>>);
+
+impl<R> Iterator for Rows<R> {
+    type Item = Result<R>;
+
+    fn next(&mut self) -> Option<Result<R>> {
+        self.0.blocking_recv()
+    }
+}
+
+/// Run the query in the background, returning the result columns and a bounded stream of rows
+pub async fn stream<DB: Source + 'static>(url: &Url, query: &str) -> Result<(Vec<Field>, Rows<DB::Row>)> {
+    let (columns_tx, columns_rx) = oneshot::channel();
+    let (rows_tx, rows_rx) = mpsc::channel(ROW_BUFFER);
+    let mut tx = Sender { columns: Some(columns_tx), rows: rows_tx };
+    let (url, query) = (url.clone(), query.to_string());
+    tokio::spawn(async move {
+        if let Err(e) = DB::fetch(&url, &query, &mut tx).await {
+            let _ = tx.rows.send(Err(e)).await;
+        }
+    });
+    let mut rows = Rows(rows_rx);
+    match columns_rx.await {
+        Ok(columns) => Ok((columns, rows)),
+        // fetch gave up before describing the result, its error is the only message left
+        Err(_) => match rows.0.recv().await {
+            Some(Err(e)) => Err(e),
+            _ => Ok((Vec::new(), rows)),
+        },
+    }
+}
+
+/// Type the columns declared without one from the row's non-NULL values, returning whether any are left untyped
+fn refine<DB>(fields: &mut [Field], rw: &DB::Row) -> bool
+where
+    DB: Database,
+    usize: ColumnIndex<DB::Row>,
+    for<'b> &'b DB::TypeInfo: Into<FieldKind>,
+{
+    let mut untyped = false;
+    for (c, fld) in fields.iter_mut().enumerate() {
+        if matches!(&fld.kind, FieldKind::UNKNOWN(typ) if typ == "null") {
+            match rw.try_get_raw(c) {
+                Ok(v) if !v.is_null() => fld.kind = v.type_info().as_ref().into(),
+                _ => untyped = true,
+            }
+        }
+    }
+    untyped
+}
+
+/// Result columns as declared, looked up before running the query so that empty results keep them
+pub trait Declared: Database {
+    /// Columns of the statement's result, with the details of their declaration SQLx type infos leave out
+    fn columns(db: &mut Self::Connection, sql: &str) -> impl Future<Output = Result<Vec<Field>>> + Send;
+}
+
+/// Type modifier of a table column, `((precision << 16) | scale) + 4` for NUMERIC and -1 when unconstrained
+const PG_TYPMOD: &str = "SELECT atttypmod FROM pg_attribute WHERE attrelid = $1 AND attnum = $2";
+
+// precision and scale of NUMERIC table columns
+impl Declared for Postgres {
+    async fn columns(db: &mut PgConnection, sql: &str) -> Result<Vec<Field>> {
+        let statement = db.prepare(sql).await?;
+        let mut fields = Vec::new();
+        for col in statement.columns() {
+            let mut fld = Field::from(col);
+            if let (FieldKind::DECIMAL(decimal), Some(table), Some(attnum)) =
+                (&mut fld.kind, col.relation_id(), col.relation_attribute_no())
+            {
+                let typmod =
+                    sqlx::query_scalar::<_, i32>(PG_TYPMOD).bind(table).bind(attnum).fetch_optional(&mut *db).await?;
+                // negative scales (PostgreSQL 15) are 11 bit two's complement
+                *decimal = typmod.filter(|&t| t >= 4).map(|t| {
+                    let t = t - 4;
+                    ((t >> 16) as u16, (((t & 0x7ff) ^ 0x400) - 0x400) as i16)
+                });
+            }
+            fields.push(fld);
+        }
+        Ok(fields)
+    }
+}
+
+// SQLx keeps the decimals of MySQL columns to itself
+impl Declared for MySql {
+    async fn columns(db: &mut MySqlConnection, sql: &str) -> Result<Vec<Field>> {
+        Ok(db.prepare(sql).await?.columns().iter().map(Field::from).collect())
+    }
+}
+
+// affinity of the declared types, which SQLx only maps when it recognises their names
+impl Declared for Sqlite {
+    async fn columns(db: &mut SqliteConnection, sql: &str) -> Result<Vec<Field>> {
+        let sql = CString::new(sql)?;
+        let mut handle = db.lock_handle().await?;
+        let db = handle.as_raw_handle().as_ptr();
+        let mut stmt = ptr::null_mut();
+        let text = |ptr: *const c_char| match ptr.is_null() {
+            true => None,
+            // SAFETY: SQLite strings are NUL terminated and live until the statement is finalized
+            false => Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()),
+        };
+        // SAFETY: the handle stays locked until the statement is finalized.
+        // Statements which fail to prepare are left to the query to report
+        unsafe {
+            if sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) != SQLITE_OK {
+                return Ok(Vec::new());
+            }
+            let fields = (0..sqlite3_column_count(stmt))
+                .map(|c| Field {
+                    name: text(sqlite3_column_name(stmt, c)).unwrap_or_default(),
+                    kind: text(sqlite3_column_decltype(stmt, c))
+                        .map_or_else(|| FieldKind::UNKNOWN("null".into()), |decl| sqlite_affinity(&decl)),
+                    ordinal: c as usize,
+                })
+                .collect();
+            sqlite3_finalize(stmt);
+            Ok(fields)
+        }
+    }
+}
+
+async fn db_fetch<'a, DB>(db_url: &'a Url, sql: &'a str, tx: &mut Sender<DB::Row>) -> Result<()>
+where
+    DB: Declared,
+    DB::Arguments<'a>: IntoArguments<'a, DB>,
+    for<'b> &'b mut DB::Connection: Executor<'b, Database = DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'b> &'b DB::Column: Into<Field>,
+    for<'b> &'b DB::TypeInfo: Into<FieldKind>,
+{
+    let mut db = DB::Connection::connect(db_url.as_str()).await?;
+    let mut fields = DB::columns(&mut db, sql).await?;
+    let mut rows = sqlx::query(sql).fetch(&mut db);
+    let mut ahead = Vec::new();
+    while let Some(rw) = rows.try_next().await? {
+        // SQLite scripts only describe their first statement, which need not be the one giving rows
+        if ahead.is_empty() && fields.is_empty() {
+            fields = rw.columns().iter().map(|col| col.into()).collect::<Vec<Field>>();
+        }
+        let untyped = refine::<DB>(&mut fields, &rw);
+        ahead.push(rw);
+        if !untyped || ahead.len() >= LOOKAHEAD {
+            break;
+        }
+    }
+    tx.columns(fields);
+    for rw in ahead {
+        tx.row(rw).await?;
+    }
+    while let Some(rw) = rows.try_next().await? {
+        tx.row(rw).await?;
+    }
+    Ok(())
+}
+
+/// Values decoded by SQLx itself, unsigned integers are left out as PostgreSQL lacks them
+pub trait Native {}
+
+impl Native for i8 {}
+impl Native for i16 {}
+impl Native for i32 {}
+impl Native for i64 {}
+impl Native for f32 {}
+impl Native for f64 {}
+impl Native for &str {}
+impl Native for &[u8] {}
+impl Native for bool {}
+impl Native for NaiveDate {}
+impl Native for NaiveDateTime {}
+impl Native for NaiveTime {}
+impl Native for DateTime<Local> {}
+impl Native for Dec {}
+impl Native for JsonValue {}
+
+/// UUID from its 16 bytes (uuid, binary(16)) or its text (char(36))
+fn uuid(value: &[u8]) -> Result<Uuid, String> {
+    Uuid::from_slice(value)
+        .or_else(|_| Uuid::try_parse_ascii(value))
+        .map_err(|_| format!("\"{}\" is not a UUID", Raw::from(value)))
+}
+
+/// Error of a cell which does not decode, naming its column
+fn decode_error<R: Row>(rw: &R, c: usize, e: impl Into<BoxDynError>) -> anyhow::Error
+where
+    usize: ColumnIndex<R>,
+{
+    anyhow!("Column \"{}\": {}", rw.column(c).name(), e.into())
+}
+
+/// Cell decoded by SQLx, its type check included
+fn try_get<'r, R, T>(rw: &'r R, c: usize) -> Result<Option<T>>
+where
+    R: Row,
+    T: Decode<'r, R::Database> + Type<R::Database>,
+    usize: ColumnIndex<R>,
+{
+    rw.try_get::<Option<T>, _>(c).map_err(|e| match e {
+        sqlx::Error::ColumnDecode { source, .. } => decode_error(rw, c, source),
+        e => e.into(),
+    })
+}
+
+/// Cell decoded by SQLx without its type check
+fn decode<'r, R, T>(rw: &'r R, c: usize) -> Result<Option<T>>
+where
+    R: Row,
+    T: Decode<'r, R::Database>,
+    usize: ColumnIndex<R>,
+{
+    let value = rw.try_get_raw(c)?;
+    match value.is_null() {
+        true => Ok(None),
+        false => T::decode(value).map(Some).map_err(|e| decode_error(rw, c, e)),
+    }
+}
+
+macro_rules! sqlx_source {
+    ($($db:ident: $row:ident),*) => {
+        $(
+            impl<'r> Cell<'r, Raw> for $row {
+                fn cell(&'r self, c: usize) -> Result<Option<Raw>> {
+                    Ok(decode::<_, &[u8]>(self, c)?.map(Raw::from))
+                }
+            }
+
+            impl<'r> Cell<'r, Uuid> for $row {
+                fn cell(&'r self, c: usize) -> Result<Option<Uuid>> {
+                    decode::<_, &[u8]>(self, c)?.map(|v| uuid(v).map_err(|e| decode_error(self, c, e))).transpose()
+                }
+            }
+
+            impl Source for $db {
+                type Row = $row;
+
+                const NAME: &'static str = <$db as Database>::NAME;
+
+                async fn fetch(url: &Url, query: &str, tx: &mut Sender<$row>) -> Result<()> {
+                    db_fetch::<$db>(url, query, tx).await
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! sqlx_native {
+    ($($db:ident: $row:ident),*) => {
+        $(
+            impl<'r, T: Native + Decode<'r, $db> + Type<$db>> Cell<'r, T> for $row {
+                fn cell(&'r self, c: usize) -> Result<Option<T>> {
+                    try_get(self, c)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! sqlx_unsigned {
+    ($db:ident: $row:ident: $($ty:ty => $native:ty),*) => {
+        $(
+            // decoded without SQLx's type check, which wants the unsigned flag MySQL may not set on YEAR and BIT
+            impl<'r> Cell<'r, $ty> for $row {
+                fn cell(&'r self, c: usize) -> Result<Option<$ty>> {
+                    decode::<_, $native>(self, c)?
+                        .map(|v| v.try_into().map_err(|e| decode_error(self, c, e)))
+                        .transpose()
+                }
+            }
+        )*
+    };
+}
+
+/// SQLite cell of one of the given storage classes: columns hold values of any class whatever their declared
+/// type, so cells are decoded by the class of their value rather than checked against the column's
+fn sqlite<'r, T: Decode<'r, Sqlite>>(rw: &'r SqliteRow, c: usize, classes: &[&str]) -> Result<Option<T>> {
+    let value = rw.try_get_raw(c)?;
+    if value.is_null() {
+        return Ok(None);
+    }
+    let class = value.type_info().name().to_string();
+    if !classes.contains(&class.as_str()) {
+        let text = decode::<_, &[u8]>(rw, c)?.map(Raw::from).map(|v| v.to_string()).unwrap_or_default();
+        return Err(decode_error(
+            rw,
+            c,
+            format!("{class} value \"{text}\" where {} is expected", classes.join(" or ")),
+        ));
+    }
+    decode(rw, c)
+}
+
+macro_rules! sqlite_cell {
+    ($($ty:ty => $($class:literal)|+),*) => {
+        $(
+            impl<'r> Cell<'r, $ty> for SqliteRow {
+                fn cell(&'r self, c: usize) -> Result<Option<$ty>> {
+                    sqlite(self, c, &[$($class),+])
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! sqlite_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl<'r> Cell<'r, $ty> for SqliteRow {
+                fn cell(&'r self, c: usize) -> Result<Option<$ty>> {
+                    sqlite::<i64>(self, c, &["INTEGER"])?
+                        .map(|v| v.try_into().map_err(|e| decode_error(self, c, e)))
+                        .transpose()
+                }
+            }
+        )*
+    };
+}
+
+impl<'r> Cell<'r, Nested> for PgRow {
+    fn cell(&'r self, c: usize) -> Result<Option<Nested>> {
+        try_get(self, c)
+    }
+}
+
+impl<'r> Cell<'r, Nested> for MySqlRow {
+    fn cell(&'r self, c: usize) -> Result<Option<Nested>> {
+        let value = self.try_get_raw(c)?;
+        match value.type_info().name() {
+            "JSON" => Ok(Record::get::<JsonValue>(self, c)?.map(Nested::Scalar)),
+            // SET, members separated by commas
+            _ => Ok(Record::get::<&str>(self, c)?
+                .map(|v| Nested::Set(v.split(',').filter(|v| !v.is_empty()).map(String::from).collect()))),
+        }
+    }
+}
+
+// arrays and composites only exist in PostgreSQL, here they can only be JSON
+impl<'r> Cell<'r, Nested> for SqliteRow {
+    fn cell(&'r self, c: usize) -> Result<Option<Nested>> {
+        Ok(Record::get::<JsonValue>(self, c)?.map(Nested::Scalar))
+    }
+}
+
+impl<'r> Cell<'r, f32> for SqliteRow {
+    fn cell(&'r self, c: usize) -> Result<Option<f32>> {
+        Ok(Record::get::<f64>(self, c)?.map(|v| v as f32))
+    }
+}
+
+sqlx_source!(Postgres: PgRow, MySql: MySqlRow, Sqlite: SqliteRow);
+sqlx_native!(Postgres: PgRow, MySql: MySqlRow);
+sqlx_unsigned!(MySql: MySqlRow: u8 => u8, u16 => u16, u32 => u32, u64 => u64);
+// PostgreSQL has no unsigned types, read the signed ones of the same size
+sqlx_unsigned!(Postgres: PgRow: u8 => i8, u16 => i16, u32 => i32, u64 => i64);
+// SQLite integers are all 64 bit, dates are text or numbers SQLx tells apart itself
+sqlite_integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+sqlite_cell!(
+    f64 => "INTEGER" | "REAL",
+    &'r str => "TEXT" | "INTEGER" | "REAL",
+    &'r [u8] => "BLOB" | "TEXT",
+    bool => "INTEGER",
+    NaiveDate => "TEXT",
+    NaiveDateTime => "TEXT" | "INTEGER" | "REAL",
+    NaiveTime => "TEXT",
+    DateTime<Local> => "TEXT" | "INTEGER" | "REAL",
+    Dec => "INTEGER" | "REAL" | "TEXT",
+    JsonValue => "TEXT" | "INTEGER" | "REAL"
+);